@@ -103,6 +103,7 @@ foreach (BeatmapCollection collection in collections)
                 ArtistUnicode = beatmap.Metadata.ArtistUnicode,
                 Source = beatmap.Metadata.Source,
                 AudioHash = audioHash,
+                Collections = [collection.Name],
             };
             if (bgHash != null)
             {
@@ -208,6 +209,9 @@ struct BeatmapCleanMetadata
 
     public HashSet<string> BGHashes { get; set; }
 
+    // Names of collections containing any difficulty of the set
+    public HashSet<string> Collections { get; set; }
+
     public override readonly int GetHashCode()
     {
         // Don't include tags
@@ -268,5 +272,6 @@ struct BeatmapCleanMetadata
             Console.Error.WriteLine($"AudioHash does not match when merging, for song {Title}");
         }
         BGHashes.UnionWith(others.BGHashes);
+        Collections.UnionWith(others.Collections);
     }
 }
@@ -0,0 +1,415 @@
+// Parser for .osu beatmap files, used to fill in metadata that the libraries
+// do not carry. Format reference: https://osu.ppy.sh/wiki/en/Client/File_formats/osu_(file_format)
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    JsonItem,
+    store::{self, file_stamp},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Difficulty {
+    pub name: String,
+    pub hp_drain_rate: f32,
+    pub circle_size: f32,
+    pub overall_difficulty: f32,
+    pub approach_rate: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Beatmap {
+    audio_filename: Option<String>,
+    preview_time: Option<u32>,
+    title: String,
+    title_unicode: String,
+    artist: String,
+    artist_unicode: String,
+    source: String,
+    tags: String,
+    creator: String,
+    beatmap_id: Option<i32>,
+    beatmap_set_id: Option<i32>,
+    difficulty: Difficulty,
+    background: Option<String>,
+    bpm: Option<(f64, f64)>,
+}
+
+enum Section {
+    Other,
+    General,
+    Metadata,
+    Difficulty,
+    Events,
+    TimingPoints,
+}
+
+fn parse_id(value: &str) -> Option<i32> {
+    value.parse().ok().filter(|&id| id > 0)
+}
+
+fn parse(path: &Path) -> Option<Beatmap> {
+    let content = std::fs::read(path).ok()?;
+    let mut beatmap = Beatmap::default();
+    let mut section = Section::Other;
+    for line in String::from_utf8_lossy(&content).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = match line {
+                "[General]" => Section::General,
+                "[Metadata]" => Section::Metadata,
+                "[Difficulty]" => Section::Difficulty,
+                "[Events]" => Section::Events,
+                "[TimingPoints]" => Section::TimingPoints,
+                _ => Section::Other,
+            };
+            continue;
+        }
+        match section {
+            Section::General | Section::Metadata | Section::Difficulty => {
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                let difficulty = &mut beatmap.difficulty;
+                match key.trim() {
+                    "AudioFilename" => beatmap.audio_filename = Some(value.to_string()),
+                    // -1 (no preview point) does not parse as u32
+                    "PreviewTime" => beatmap.preview_time = value.parse().ok(),
+                    "Title" => beatmap.title = value.to_string(),
+                    "TitleUnicode" => beatmap.title_unicode = value.to_string(),
+                    "Artist" => beatmap.artist = value.to_string(),
+                    "ArtistUnicode" => beatmap.artist_unicode = value.to_string(),
+                    "Source" => beatmap.source = value.to_string(),
+                    "Tags" => beatmap.tags = value.to_string(),
+                    "Creator" => beatmap.creator = value.to_string(),
+                    "Version" => difficulty.name = value.to_string(),
+                    "BeatmapID" => beatmap.beatmap_id = parse_id(value),
+                    "BeatmapSetID" => beatmap.beatmap_set_id = parse_id(value),
+                    "HPDrainRate" => difficulty.hp_drain_rate = value.parse().unwrap_or(0.0),
+                    "CircleSize" => difficulty.circle_size = value.parse().unwrap_or(0.0),
+                    "OverallDifficulty" => {
+                        difficulty.overall_difficulty = value.parse().unwrap_or(0.0)
+                    }
+                    "ApproachRate" => difficulty.approach_rate = value.parse().unwrap_or(0.0),
+                    _ => {}
+                }
+            }
+            Section::Events => {
+                // 0,0,"bg.jpg",0,0
+                let fields: Vec<&str> = line.split(',').collect();
+                if beatmap.background.is_none()
+                    && fields.len() >= 3
+                    && (fields[0] == "0" || fields[0] == "Background")
+                {
+                    beatmap.background = Some(fields[2].trim_matches('"').to_string());
+                }
+            }
+            Section::TimingPoints => {
+                // time,beatLength,meter,sampleSet,sampleIndex,volume,uninherited,effects
+                let fields: Vec<&str> = line.split(',').collect();
+                let Some(beat_length) = fields.get(1).and_then(|f| f.trim().parse::<f64>().ok())
+                else {
+                    continue;
+                };
+                // older formats have no uninherited field, and inherited points have negative beat length
+                let uninherited = fields.get(6).is_none_or(|f| f.trim() == "1");
+                if uninherited && beat_length > 0.0 {
+                    let bpm = 60000.0 / beat_length;
+                    beatmap.bpm = Some(match beatmap.bpm {
+                        Some((min, max)) => (min.min(bpm), max.max(bpm)),
+                        None => (bpm, bpm),
+                    });
+                }
+            }
+            Section::Other => {}
+        }
+    }
+    Some(beatmap)
+}
+
+/// Returns the audio and background file names referenced by a .osu file
+pub fn referenced_files(path: &Path) -> (Option<String>, Option<String>) {
+    match parse(path) {
+        Some(beatmap) => (beatmap.audio_filename, beatmap.background),
+        None => (None, None),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    stamp: String,
+    beatmap: Beatmap,
+}
+
+/// Parsed .osu files by path and stamp, so that starting up does not read all of them again
+#[derive(Default)]
+pub struct Cache {
+    loaded: HashMap<PathBuf, Cached>,
+    /// Entries of this run, the only ones saved so that removed files are dropped
+    used: HashMap<PathBuf, Cached>,
+}
+
+impl Cache {
+    pub fn load(path: &Path) -> Self {
+        Self {
+            loaded: store::load_json(path),
+            used: HashMap::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        store::save_json(path, &self.used)
+    }
+
+    fn parse(&mut self, path: &Path) -> Option<Beatmap> {
+        let stamp = file_stamp(path).ok()?;
+        if let Some(cached) = self.used.get(path)
+            && cached.stamp == stamp
+        {
+            return Some(cached.beatmap.clone());
+        }
+        let beatmap = match self.loaded.remove(path) {
+            Some(cached) if cached.stamp == stamp => cached.beatmap,
+            _ => parse(path)?,
+        };
+        self.used.insert(
+            path.to_path_buf(),
+            Cached {
+                stamp,
+                beatmap: beatmap.clone(),
+            },
+        );
+        Some(beatmap)
+    }
+
+    /// Fills in `item`'s metadata from its beatmap set's .osu files.
+    /// `resolve` maps a file name inside the set to a key for `JsonItem::get_file_path`.
+    pub fn enrich<P: AsRef<Path>>(
+        &mut self,
+        item: &mut JsonItem,
+        osu_files: &[P],
+        resolve: impl Fn(&str) -> Option<String>,
+    ) {
+        for path in osu_files {
+            let Some(beatmap) = self.parse(path.as_ref()) else {
+                continue;
+            };
+            let metadata = &mut item.metadata;
+            for (field, value) in [
+                (&mut metadata.title, beatmap.title),
+                (&mut metadata.title_unicode, beatmap.title_unicode),
+                (&mut metadata.artist, beatmap.artist),
+                (&mut metadata.artist_unicode, beatmap.artist_unicode),
+                (&mut metadata.source, beatmap.source),
+                (&mut metadata.tags, beatmap.tags),
+                (&mut metadata.creator, beatmap.creator),
+            ] {
+                if field.is_empty() {
+                    *field = value;
+                }
+            }
+            if metadata.preview_time.is_none() {
+                metadata.preview_time = beatmap.preview_time;
+            }
+            if metadata.beatmap_set_id.is_none() {
+                metadata.beatmap_set_id = beatmap.beatmap_set_id;
+            }
+            if let Some(id) = beatmap.beatmap_id
+                && !metadata.beatmap_ids.contains(&id)
+            {
+                metadata.beatmap_ids.push(id);
+            }
+            if let Some((min, max)) = beatmap.bpm {
+                metadata.bpm = Some(match metadata.bpm {
+                    Some((cur_min, cur_max)) => (cur_min.min(min), cur_max.max(max)),
+                    None => (min, max),
+                });
+            }
+            if !metadata
+                .difficulties
+                .iter()
+                .any(|d| d.name == beatmap.difficulty.name)
+            {
+                metadata.difficulties.push(beatmap.difficulty);
+            }
+            if item.bg_hashes.is_empty()
+                && let Some(bg) = beatmap.background.as_deref().and_then(&resolve)
+            {
+                item.bg_hashes.push(bg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{FileLayout, Metadata};
+
+    const HARD: &str = "\u{feff}osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+PreviewTime: 61234
+
+[Metadata]
+Title:Song
+TitleUnicode:歌
+Artist:Artist
+Creator:mapper
+Version:Hard
+Source:
+Tags:tag1 tag2
+BeatmapID:12
+BeatmapSetID:34
+
+[Difficulty]
+HPDrainRate:5
+CircleSize:4
+OverallDifficulty:8.5
+ApproachRate:9
+
+[Events]
+//Background and Video events
+0,0,\"bg.jpg\",0,0
+
+[TimingPoints]
+100,500,4,2,0,60,1,0
+20000,-50,4,2,0,60,0,0
+40000,250,4,2,0,60,1,0
+";
+
+    const EASY: &str = "osu file format v5
+
+[General]
+AudioFilename: audio.mp3
+PreviewTime: -1
+
+[Metadata]
+Title:Song
+Version:Easy
+BeatmapID:0
+
+[Events]
+Background,0,\"other.png\"
+
+[TimingPoints]
+100,400
+";
+
+    fn item() -> JsonItem {
+        JsonItem {
+            audio_hash: "audio.mp3".to_string(),
+            bg_hashes: Vec::new(),
+            metadata: Metadata::default(),
+            layout: FileLayout::default(),
+        }
+    }
+
+    /// Writes the .osu files into a folder of their own, returning their paths
+    fn write(name: &str, files: &[(&str, &str)]) -> Vec<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("ccp-beatmap-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.join(name);
+                fs::write(&path, content).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_osu_file() {
+        let paths = write("parse", &[("hard.osu", HARD), ("easy.osu", EASY)]);
+        let hard = parse(&paths[0]).unwrap();
+        let easy = parse(&paths[1]).unwrap();
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+
+        assert_eq!(hard.audio_filename.as_deref(), Some("audio.mp3"));
+        assert_eq!(hard.preview_time, Some(61234));
+        assert_eq!(hard.title, "Song");
+        assert_eq!(hard.title_unicode, "歌");
+        assert_eq!(hard.creator, "mapper");
+        assert_eq!(hard.tags, "tag1 tag2");
+        assert_eq!(hard.beatmap_id, Some(12));
+        assert_eq!(hard.beatmap_set_id, Some(34));
+        assert_eq!(hard.difficulty.name, "Hard");
+        assert_eq!(hard.difficulty.overall_difficulty, 8.5);
+        assert_eq!(hard.background.as_deref(), Some("bg.jpg"));
+        // the inherited point (negative beat length) is not a BPM change
+        assert_eq!(hard.bpm, Some((120.0, 240.0)));
+
+        // no preview point, no IDs, old style background and timing points
+        assert_eq!(easy.preview_time, None);
+        assert_eq!(easy.beatmap_id, None);
+        assert_eq!(easy.background.as_deref(), Some("other.png"));
+        assert_eq!(easy.bpm, Some((150.0, 150.0)));
+    }
+
+    #[test]
+    fn merges_difficulties() {
+        let paths = write("enrich", &[("hard.osu", HARD), ("easy.osu", EASY)]);
+        let mut item = item();
+        item.metadata.title = "Library Title".to_string();
+        Cache::default().enrich(&mut item, &paths, |name| Some(format!("set/{}", name)));
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+
+        let metadata = &item.metadata;
+        // what the library has is kept
+        assert_eq!(metadata.title, "Library Title");
+        assert_eq!(metadata.title_unicode, "歌");
+        assert_eq!(metadata.preview_time, Some(61234));
+        assert_eq!(metadata.beatmap_ids, [12]);
+        assert_eq!(metadata.bpm, Some((120.0, 240.0)));
+        let names: Vec<_> = metadata.difficulties.iter().map(|d| &d.name).collect();
+        assert_eq!(names, ["Hard", "Easy"]);
+        assert_eq!(item.bg_hashes, ["set/bg.jpg"]);
+    }
+
+    #[test]
+    fn cache_reparses_changed_files() {
+        let paths = write("cache", &[("hard.osu", HARD)]);
+        let cache_path = paths[0].with_file_name("beatmaps.json");
+        let mut cache = Cache::load(&cache_path);
+        cache.enrich(&mut item(), &paths, |_| None);
+        cache.save(&cache_path).unwrap();
+
+        // a cached file is not read again
+        let mut cache = Cache::load(&cache_path);
+        let stamp = file_stamp(&paths[0]).unwrap();
+        cache.loaded.get_mut(&paths[0]).unwrap().beatmap.title = "Cached".to_string();
+        let mut cached = item();
+        cache.enrich(&mut cached, &paths, |_| None);
+        assert_eq!(cached.metadata.title, "Cached");
+        assert_eq!(cache.used[&paths[0]].stamp, stamp);
+
+        // but a changed one is
+        let mut cache = Cache::load(&cache_path);
+        cache.loaded.get_mut(&paths[0]).unwrap().stamp = "0 0".to_string();
+        let mut changed = item();
+        cache.enrich(&mut changed, &paths, |_| None);
+        assert_eq!(changed.metadata.title, "Song");
+
+        // and a removed one is dropped
+        fs::remove_file(&paths[0]).unwrap();
+        let mut cache = Cache::load(&cache_path);
+        cache.enrich(&mut item(), &paths, |_| None);
+        assert!(cache.used.is_empty());
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+}
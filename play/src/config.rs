@@ -0,0 +1,34 @@
+// Player settings remembered across runs, kept in the XDG config directory.
+// Unlike the session, these are restored even without `--resume`. Settings
+// without keys in the player, like seek steps, can be edited in the file.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// mpv volume, 0 to MAX_VOLUME
+    pub volume: f64,
+    pub muted: bool,
+    /// Seconds seeked by left/right
+    pub seek_step: f64,
+    /// With shift
+    pub small_seek_step: f64,
+    /// With ctrl
+    pub large_seek_step: f64,
+    /// mpv audio device picked last, None for mpv's default
+    pub audio_device: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            volume: 100.0,
+            muted: false,
+            seek_step: 5.0,
+            small_seek_step: 1.0,
+            large_seek_step: 30.0,
+            audio_device: None,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Named cue points within songs (like kiai start or drop), kept in the XDG data
+// directory by audio file key.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cue {
+    pub name: String,
+    /// In seconds
+    pub time: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Cues {
+    /// Cues of each song, sorted by time
+    pub songs: HashMap<String, Vec<Cue>>,
+}
+
+impl Cues {
+    pub fn get(&self, key: &str) -> &[Cue] {
+        self.songs.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds a cue in time order, returning its index
+    pub fn add(&mut self, key: &str, cue: Cue) -> usize {
+        let cues = self.songs.entry(key.to_string()).or_default();
+        let i = cues.partition_point(|c| c.time <= cue.time);
+        cues.insert(i, cue);
+        i
+    }
+
+    pub fn rename(&mut self, key: &str, i: usize, name: &str) {
+        if let Some(cue) = self.songs.get_mut(key).and_then(|cues| cues.get_mut(i)) {
+            cue.name = name.to_string();
+        }
+    }
+
+    pub fn remove(&mut self, key: &str, i: usize) {
+        if let Some(cues) = self.songs.get_mut(key)
+            && i < cues.len()
+        {
+            cues.remove(i);
+            if cues.is_empty() {
+                self.songs.remove(key);
+            }
+        }
+    }
+}
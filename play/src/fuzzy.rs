@@ -0,0 +1,157 @@
+// Fuzzy matching for search: pattern characters have to appear in order,
+// and matches with consecutive characters and at word starts score higher.
+
+use crate::normalize::{normalize, romanize};
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 12;
+const BONUS_WORD_START: i64 = 10;
+const PENALTY_GAP: i64 = 1;
+/// Matches spreading wider than pattern length times this are rejected,
+/// so that short patterns do not match random letters of long texts
+const MAX_SPREAD: usize = 3;
+
+fn is_word_start(text: &[char], i: usize) -> bool {
+    i == 0
+        || !text[i - 1].is_alphanumeric()
+        || (text[i - 1].is_lowercase() && text[i].is_uppercase())
+}
+
+/// Best match of `pattern` in normalized `text`, with original char indices
+fn match_chars(
+    pattern: &[char],
+    text: &[(char, usize)],
+    original: &[char],
+) -> Option<(i64, Vec<usize>)> {
+    let max_span = pattern.len() * MAX_SPREAD;
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..text.len()).filter(|&i| text[i].0 == pattern[0]) {
+        let mut positions = vec![start];
+        let mut i = start + 1;
+        for &p in &pattern[1..] {
+            while i < text.len() && text[i].0 != p && i - start < max_span {
+                i += 1;
+            }
+            if i >= text.len() || i - start >= max_span {
+                break;
+            }
+            positions.push(i);
+            i += 1;
+        }
+        if positions.len() < pattern.len() {
+            // later starts only have less text left
+            if i >= text.len() {
+                break;
+            }
+            continue;
+        }
+        let mut score = 0;
+        for (k, &pos) in positions.iter().enumerate() {
+            score += SCORE_MATCH;
+            // only the first of chars from the same original char (like romaji of a kana)
+            let origin = text[pos].1;
+            if (pos == 0 || text[pos - 1].1 != origin) && is_word_start(original, origin) {
+                score += BONUS_WORD_START;
+            }
+            if k > 0 {
+                let gap = pos - positions[k - 1] - 1;
+                if gap == 0 {
+                    score += BONUS_CONSECUTIVE;
+                } else {
+                    score -= gap as i64 * PENALTY_GAP;
+                }
+            }
+        }
+        if best.as_ref().is_none_or(|(s, _)| score > *s) {
+            best = Some((score, positions));
+        }
+    }
+    best.map(|(score, positions)| {
+        let mut origins: Vec<usize> = positions.into_iter().map(|p| text[p].1).collect();
+        origins.dedup();
+        (score, origins)
+    })
+}
+
+/// Returns score and matched char indices of the best match of `pattern` in `text`.
+/// Both are normalized, and kana in `text` also match their romaji.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let pattern: Vec<char> = normalize(pattern).into_iter().map(|(c, _)| c).collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let original: Vec<char> = text.chars().collect();
+    let text = normalize(text);
+    let direct = match_chars(&pattern, &text, &original);
+    // kana have no spaces between words, unlike romaji typed like "yoru ni kakeru"
+    let compact: Vec<char> = pattern.iter().copied().filter(|c| *c != ' ').collect();
+    let romaji = romanize(&text)
+        .filter(|_| !compact.is_empty())
+        .and_then(|romaji| match_chars(&compact, &romaji, &original));
+    match (direct, romaji) {
+        (Some(a), Some(b)) => Some(if b.0 > a.0 { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(pattern: &str, text: &str) -> Option<Vec<usize>> {
+        fuzzy_match(pattern, text).map(|(_, positions)| positions)
+    }
+
+    fn score(pattern: &str, text: &str) -> i64 {
+        fuzzy_match(pattern, text).unwrap().0
+    }
+
+    #[test]
+    fn matches_in_order() {
+        assert_eq!(fuzzy_match("", "anything"), Some((0, Vec::new())));
+        assert_eq!(positions("abc", "xaxbxc"), Some(vec![1, 3, 5]));
+        assert_eq!(positions("ba", "ab"), None);
+        assert_eq!(positions("abcd", "abc"), None);
+        assert_eq!(positions("x", ""), None);
+        // the best of several starts
+        assert_eq!(positions("ni", "Yoru ni Kakeru"), Some(vec![5, 6]));
+        assert_eq!(positions("ka", "Yoru ni Kakeru"), Some(vec![8, 9]));
+    }
+
+    #[test]
+    fn ignores_case_and_width() {
+        assert_eq!(positions("YOA", "yoasobi"), Some(vec![0, 1, 2]));
+        assert_eq!(positions("abc", "ＡＢＣ"), Some(vec![0, 1, 2]));
+        assert_eq!(positions("cafe", "Café"), Some(vec![0, 1, 2, 3]));
+        assert_eq!(positions("かける", "カケル"), Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn rejects_spread_out_matches() {
+        // "ab" may span at most 6 chars
+        assert!(positions("ab", "a1234b").is_some());
+        assert!(positions("ab", "a12345b").is_none());
+        assert!(positions("ab", "a12345b ab").is_some());
+    }
+
+    #[test]
+    fn matches_romaji_of_kana() {
+        assert_eq!(positions("kakeru", "カケル"), Some(vec![0, 1, 2]));
+        assert_eq!(positions("yoru ni kakeru", "夜にカケル"), None);
+        assert_eq!(
+            positions("yoru ni kakeru", "よるにかける"),
+            Some(vec![0, 1, 2, 3, 4, 5])
+        );
+        // small kana join the one before, "きょ" is "kyo"
+        assert_eq!(positions("kyou", "今日はきょう"), Some(vec![3, 4, 5]));
+    }
+
+    #[test]
+    fn prefers_consecutive_and_word_starts() {
+        assert!(score("abc", "abc") > score("abc", "a b c"));
+        assert!(score("abc", "a b c") > score("abc", "xaxbxc"));
+        assert!(score("kk", "Kuro Kakeru") > score("kk", "kikk"));
+        // camelCase humps are word starts
+        assert!(score("b", "aBc") > score("b", "abc"));
+    }
+}
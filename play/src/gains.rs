@@ -0,0 +1,33 @@
+// Per-song volume offsets in dB, for songs still too loud (or quiet) after
+// loudness normalization. Kept in the XDG data directory, keyed by audio file key.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Offsets are limited to this many dB either way
+pub const MAX_GAIN: f64 = 12.0;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Gains {
+    pub songs: HashMap<String, f64>,
+}
+
+impl Gains {
+    pub fn get(&self, key: &str) -> f64 {
+        self.songs.get(key).copied().unwrap_or(0.0)
+    }
+
+    /// Changes the offset of a song, returning the new one
+    pub fn add(&mut self, key: &str, delta: f64) -> f64 {
+        let gain = (self.get(key) + delta).clamp(-MAX_GAIN, MAX_GAIN);
+        // round away float error from repeated steps
+        let gain = (gain * 10.0).round() / 10.0;
+        if gain == 0.0 {
+            self.songs.remove(key);
+        } else {
+            self.songs.insert(key.to_string(), gain);
+        }
+        gain
+    }
+}
@@ -0,0 +1,337 @@
+// Library sources outside of an osu! install: .osz archives (extracted into
+// the cache directory, and again when they change) and plain folders of music files.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{FileLayout, JsonItem, Metadata, beatmap, store::file_stamp};
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "flac", "m4a", "opus", "wav"];
+const COVER_NAMES: &[&str] = &["cover.jpg", "cover.png", "folder.jpg", "folder.png"];
+/// Written into an extracted .osz, holding the stamp of the archive it came from
+const STAMP_FILE: &str = ".osz-stamp";
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+pub fn is_osz(path: &Path) -> bool {
+    has_extension(path, &["osz"])
+}
+
+/// Finds a file in `dir` by case-insensitive name, as osu! does
+pub fn find_file(dir: &Path, name: &str) -> Option<String> {
+    // most names match as they are, without listing the folder
+    if dir.join(name).is_file() {
+        return Some(name.to_string());
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .find(|f| f.eq_ignore_ascii_case(name))
+}
+
+/// FNV-1a. Unlike `DefaultHasher` it is the same across builds, which matters as song keys are made from it.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Names the cache folder of an .osz after its full path, so that
+/// archives with the same file name in different folders are kept apart
+fn osz_key(osz: &Path) -> io::Result<String> {
+    let path = fs::canonicalize(osz)?;
+    Ok(format!(
+        "{:016x}",
+        stable_hash(path.as_os_str().as_encoded_bytes())
+    ))
+}
+
+/// Extracts .osu files and the audio and backgrounds they use, replacing an outdated extraction
+fn extract_osz(osz: &Path, target: &Path, stamp: &str) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(osz)?).map_err(io::Error::other)?;
+    // extract to a temporary folder first, so that an interrupted extraction is not cached
+    let tmp = target.with_extension("partial");
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(&tmp)?;
+
+    let mut wanted = Vec::new();
+    for pass in 0..2 {
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(io::Error::other)?;
+            let Some(name) = file.enclosed_name() else {
+                continue;
+            };
+            let is_osu = has_extension(&name, &["osu"]);
+            let needed = if pass == 0 {
+                is_osu
+            } else {
+                !is_osu
+                    && wanted
+                        .iter()
+                        .any(|w: &String| name.to_string_lossy().eq_ignore_ascii_case(w))
+            };
+            if !file.is_file() || !needed {
+                continue;
+            }
+            let out = tmp.join(&name);
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut fs::File::create(&out)?)?;
+            if is_osu {
+                let (audio, background) = beatmap::referenced_files(&out);
+                wanted.extend(audio);
+                wanted.extend(background);
+            }
+        }
+    }
+    fs::write(tmp.join(STAMP_FILE), stamp)?;
+    if target.exists() {
+        fs::remove_dir_all(target)?;
+    }
+    fs::rename(tmp, target)
+}
+
+fn load_osz(
+    osz: &Path,
+    cache_path: &Path,
+    beatmap_cache: &mut beatmap::Cache,
+) -> io::Result<Option<JsonItem>> {
+    let name = osz_key(osz)?;
+    let stamp = file_stamp(osz)?;
+    let target = cache_path.join(&name);
+    if fs::read_to_string(target.join(STAMP_FILE)).ok().as_deref() != Some(stamp.as_str()) {
+        extract_osz(osz, &target, &stamp)?;
+    }
+    let osu_files: Vec<PathBuf> = fs::read_dir(&target)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| has_extension(path, &["osu"]))
+        .collect();
+    let Some(audio) = osu_files
+        .iter()
+        .find_map(|path| beatmap::referenced_files(path).0)
+        .and_then(|audio| find_file(&target, &audio))
+    else {
+        return Ok(None);
+    };
+    let mut item = JsonItem {
+        audio_hash: format!("{}/{}", name, audio),
+        bg_hashes: Vec::new(),
+        metadata: Metadata::default(),
+        layout: FileLayout::Relative(cache_path.to_path_buf()),
+    };
+    beatmap_cache.enrich(&mut item, &osu_files, |file| {
+        find_file(&target, file).map(|file| format!("{}/{}", name, file))
+    });
+    Ok(Some(item))
+}
+
+/// Makes an item from a music file, named like "title - artist.mp3" (as convert.py does) or just "title.mp3"
+fn load_music(base: &Path, path: &Path) -> JsonItem {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (title, artist) = match stem.split_once(" - ") {
+        Some((title, artist)) => (title.to_string(), artist.to_string()),
+        None => (stem, String::new()),
+    };
+    let relative = |path: &Path| {
+        path.strip_prefix(base)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    };
+    let dir = path.parent().unwrap_or(base);
+    let bg_hashes = COVER_NAMES
+        .iter()
+        .find_map(|cover| find_file(dir, cover))
+        .map(|cover| relative(&dir.join(cover)))
+        .into_iter()
+        .collect();
+    JsonItem {
+        audio_hash: relative(path),
+        bg_hashes,
+        metadata: Metadata {
+            title,
+            artist,
+            source: dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            ..Default::default()
+        },
+        layout: FileLayout::Relative(base.to_path_buf()),
+    }
+}
+
+fn walk(
+    base: &Path,
+    dir: &Path,
+    cache_path: &Path,
+    beatmap_cache: &mut beatmap::Cache,
+    items: &mut Vec<JsonItem>,
+) -> io::Result<()> {
+    let mut entries: Vec<(PathBuf, fs::FileType)> = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.path(), entry.file_type().ok()?))
+        })
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, file_type) in entries {
+        // one broken file or unreadable folder should not hide the rest of the library
+        if file_type.is_dir() {
+            if let Err(e) = walk(base, &path, cache_path, beatmap_cache, items) {
+                eprintln!("skipping {}: {}", path.display(), e);
+            }
+        } else if path.is_dir() {
+            // symlinked folders are not followed, as they may link back up and never end
+        } else if is_osz(&path) {
+            match load_osz(&path, cache_path, beatmap_cache) {
+                Ok(item) => items.extend(item),
+                Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+            }
+        } else if has_extension(&path, AUDIO_EXTENSIONS) {
+            items.push(load_music(base, &path));
+        }
+    }
+    Ok(())
+}
+
+/// Loads an .osz file, or a folder containing .osz and music files.
+/// .osz archives are extracted under `cache_path`.
+pub fn load_songs(
+    path: &Path,
+    cache_path: &Path,
+    beatmap_cache: &mut beatmap::Cache,
+) -> io::Result<Vec<JsonItem>> {
+    let mut items = Vec::new();
+    if path.is_dir() {
+        walk(path, path, cache_path, beatmap_cache, &mut items)?;
+    } else if is_osz(path) {
+        items.extend(load_osz(path, cache_path, beatmap_cache)?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    const OSU: &str = "osu file format v14
+
+[General]
+AudioFilename: Audio.mp3
+
+[Metadata]
+Title:Song
+Artist:Artist
+Version:Hard
+
+[Events]
+0,0,\"bg.jpg\",0,0
+";
+
+    fn write_osz(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn stable_hash_is_fnv1a() {
+        assert_eq!(stable_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn loads_folder() {
+        let base = std::env::temp_dir().join(format!("ccp-local-{}", std::process::id()));
+        let library = base.join("library");
+        let cache = base.join("cache");
+        fs::create_dir_all(library.join("Album")).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(library.join("Album").join("Title - Artist.flac"), "").unwrap();
+        fs::write(library.join("Album").join("Cover.JPG"), "").unwrap();
+        fs::write(library.join("notes.txt"), "").unwrap();
+        // the .osu file names its audio in another case, and a file not used is left out
+        let osz = library.join("set.osz");
+        write_osz(
+            &osz,
+            &[
+                ("song (Hard).osu", OSU),
+                ("audio.mp3", "audio"),
+                ("BG.jpg", "bg"),
+                ("unused.wav", ""),
+            ],
+        );
+        // a broken archive does not hide the rest of the library
+        fs::write(library.join("broken.osz"), "not a zip").unwrap();
+        // nor does a folder linking back up
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&library, library.join("Album").join("loop")).unwrap();
+
+        let load = || load_songs(&library, &cache, &mut beatmap::Cache::default()).unwrap();
+        let songs = load();
+        assert_eq!(songs.len(), 2);
+
+        let music = &songs[0];
+        assert_eq!(
+            music.audio_hash,
+            Path::new("Album")
+                .join("Title - Artist.flac")
+                .to_string_lossy()
+        );
+        assert_eq!(
+            music.bg_hashes,
+            [Path::new("Album").join("Cover.JPG").to_string_lossy()]
+        );
+        assert_eq!(music.metadata.title, "Title");
+        assert_eq!(music.metadata.artist, "Artist");
+        assert_eq!(music.metadata.source, "Album");
+
+        let set = &songs[1];
+        let key = osz_key(&osz).unwrap();
+        assert_eq!(set.audio_hash, format!("{}/audio.mp3", key));
+        assert_eq!(set.bg_hashes, [format!("{}/BG.jpg", key)]);
+        assert_eq!(set.metadata.title, "Song");
+        let extracted = cache.join(&key);
+        assert!(!extracted.join("unused.wav").exists());
+        assert_eq!(
+            fs::read_to_string(extracted.join("audio.mp3")).unwrap(),
+            "audio"
+        );
+
+        // an updated archive is extracted again
+        write_osz(
+            &osz,
+            &[("song (Hard).osu", OSU), ("audio.mp3", "new audio")],
+        );
+        let songs = load();
+        assert!(songs[1].bg_hashes.is_empty());
+        assert_eq!(
+            fs::read_to_string(extracted.join("audio.mp3")).unwrap(),
+            "new audio"
+        );
+        assert!(!extracted.join("BG.jpg").exists());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+// Loudness analysis (EBU R128 integrated loudness and true peak) of songs, done
+// once by `play analyze` with ffmpeg's loudnorm filter, and kept in the XDG cache
+// directory by audio file key. Playback then applies a static ReplayGain-style
+// gain, instead of the single-pass live loudnorm filter.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Loudness songs are normalized to, in LUFS, same as the live filter
+pub const TARGET: f64 = -14.0;
+/// Gains are lowered to keep true peaks below this, in dBTP
+pub const MAX_PEAK: f64 = -2.0;
+/// Live filter for songs not analyzed
+pub const LIVE_FILTER: &str = "lavfi=[loudnorm=I=-14:TP=-2:LRA=11]";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Analysis {
+    /// In LUFS
+    pub integrated: f64,
+    /// In dBTP
+    pub true_peak: f64,
+}
+
+impl Analysis {
+    /// Gain in dB bringing the song to TARGET, without peaks going over MAX_PEAK
+    pub fn gain(&self) -> f64 {
+        (TARGET - self.integrated).min(MAX_PEAK - self.true_peak)
+    }
+
+    /// Loudness of songs played one after another, like ReplayGain's album gain
+    pub fn combine<'a>(analyses: impl IntoIterator<Item = &'a Analysis>) -> Option<Analysis> {
+        let mut count = 0;
+        let mut energy = 0.0;
+        let mut true_peak = f64::MIN;
+        for analysis in analyses {
+            count += 1;
+            energy += 10f64.powf(analysis.integrated / 10.0);
+            true_peak = true_peak.max(analysis.true_peak);
+        }
+        (count > 0).then(|| Analysis {
+            integrated: 10.0 * (energy / count as f64).log10(),
+            true_peak,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Loudness {
+    pub songs: HashMap<String, Analysis>,
+}
+
+impl Loudness {
+    pub fn get(&self, key: &str) -> Option<&Analysis> {
+        self.songs.get(key)
+    }
+}
+
+/// Parses a number from loudnorm's JSON summary, where numbers are strings
+fn field(summary: &serde_json::Value, name: &str) -> io::Result<f64> {
+    summary[name]
+        .as_str()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| io::Error::other(format!("no {} in ffmpeg output", name)))
+}
+
+/// Measures a file by decoding it with ffmpeg, which has to be in PATH
+pub fn analyze(path: &Path) -> io::Result<Analysis> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-nostdin", "-i"])
+        .arg(path)
+        .args([
+            "-vn",
+            "-af",
+            "loudnorm=I=-14:TP=-2:LRA=11:print_format=json",
+            "-f",
+            "null",
+            "-",
+        ])
+        .stdout(Stdio::null())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(io::Error::other(format!("ffmpeg failed: {}", reason)));
+    }
+    // the summary is the last thing printed
+    let start = stderr.rfind('{').unwrap_or(stderr.len());
+    let end = stderr.rfind('}').map_or(0, |i| i + 1);
+    let summary: serde_json::Value = stderr
+        .get(start..end)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .ok_or_else(|| io::Error::other("no loudness summary in ffmpeg output"))?;
+    // silence is -inf, which JSON cannot store; -70 LUFS is R128's absolute gate
+    Ok(Analysis {
+        integrated: field(&summary, "input_i")?.max(-70.0),
+        true_peak: field(&summary, "input_tp")?.max(-70.0),
+    })
+}
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use crossterm::{
     event::{self},
     terminal::{
@@ -12,48 +12,192 @@ use libmpv::{
     events::{Event, PropertyData},
     mpv_end_file_reason,
 };
-use rand::prelude::SliceRandom;
+use rand::{
+    SeedableRng,
+    distributions::{Distribution, WeightedIndex},
+    prelude::SliceRandom,
+    rngs::StdRng,
+};
 use ratatui::{
     Terminal,
     backend::CrosstermBackend,
     layout::Layout,
     style::{Modifier, Style},
-    text::Line,
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
 };
 use ratatui_image::{StatefulImage, picker::Picker, protocol::StatefulProtocol};
-use serde::{Deserialize, Deserializer};
-use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, PlatformConfig};
+use serde::{Deserialize, Deserializer, Serialize};
+use souvlaki::{LoopStatus, MediaControlEvent, MediaControls, MediaMetadata, PlatformConfig};
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
     io::{self, stdout},
     panic::{set_hook, take_hook},
     path::{Path, PathBuf},
-    sync::mpsc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use tui_input::backend::crossterm::EventHandler;
 use url::Url;
 
+mod beatmap;
+mod config;
+mod cues;
+mod fuzzy;
+mod gains;
+mod local;
+mod loudness;
+mod normalize;
+mod playlist_file;
+mod playlists;
+mod query;
+mod ratings;
+mod realm;
+mod screens;
+mod session;
+mod stable;
+mod stats;
+mod store;
+
+use config::Config;
+use cues::{Cue, Cues};
+use gains::Gains;
+use loudness::Loudness;
+use playlists::Playlist;
+use ratings::{Rating, Ratings};
+use session::Session;
+use stats::{End, Stats};
+
 const APP_ID: &str = "moe.taoky.clicking-circles-player";
 const APP_NAME: &str = "clicking-circles-player";
 const APP_DISPLAY_NAME: &str = "Clicking Circles Player";
+const HISTORY_LIMIT: usize = 200;
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// mpv's default volume-max
+const MAX_VOLUME: f64 = 130.0;
+const VOLUME_STEP: f64 = 5.0;
+/// In dB
+const GAIN_STEP: f64 = 1.0;
+/// End key seeks to this many seconds before the end
+const END_SECONDS: f64 = 10.0;
+const MIN_SPEED: f64 = 0.5;
+const MAX_SPEED: f64 = 2.0;
+const SPEED_STEP: f64 = 0.05;
+/// Seconds played of each song in preview mode, unless given with --preview
+const DEFAULT_PREVIEW_LENGTH: f64 = 15.0;
+/// Seconds before a crossfade that the ending song is loaded into the second mpv instance
+const CROSSFADE_LOAD_AHEAD: f64 = 2.0;
 
 enum InternalEvent {
     Pos(f64),
     Duration(f64),
     Eof,
+    /// mpv went on to the preloaded song
+    Advanced,
+    /// Audio devices mpv found, on startup and when they change
+    AudioDevices(Vec<AudioDevice>),
+    /// mpv could not switch devices: the device it still plays on, and the error
+    AudioDeviceFailed(String, String),
     Quit,
 }
 
+/// Entry of mpv's audio-device-list
+#[derive(Deserialize, Debug, Clone)]
+struct AudioDevice {
+    /// Like "pulse/alsa_output.usb-..." or "auto"
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+/// Seek target, done with mpv's seek command
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Seek {
+    /// Seconds from the current position
+    Relative(f64),
+    /// Seconds from the start, or from the end if negative
+    Absolute(f64),
+    /// Percent of the song
+    Percent(f64),
+}
+
+impl Seek {
+    /// Parses timestamps like "1:23", "1:02:03.5" and "83", relative ones like
+    /// "+10" and "-1:00", and percents like "50%"
+    fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        if let Some(percent) = input.strip_suffix('%') {
+            let percent: f64 = percent.trim().parse().ok()?;
+            return (0.0..=100.0)
+                .contains(&percent)
+                .then_some(Seek::Percent(percent));
+        }
+        let (sign, time) = match input.as_bytes().first()? {
+            b'+' => (Some(1.0), &input[1..]),
+            b'-' => (Some(-1.0), &input[1..]),
+            _ => (None, input),
+        };
+        let parts: Vec<&str> = time.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        let mut secs = 0.0;
+        for (i, part) in parts.iter().enumerate() {
+            let value: f64 = part.trim().parse().ok()?;
+            // minutes and seconds after the first part are below 60,
+            // and only the last part has a fraction
+            if !value.is_finite()
+                || value < 0.0
+                || (i > 0 && value >= 60.0)
+                || (i + 1 < parts.len() && value.fract() != 0.0)
+            {
+                return None;
+            }
+            secs = secs * 60.0 + value;
+        }
+        Some(match sign {
+            Some(sign) => Seek::Relative(sign * secs),
+            None => Seek::Absolute(secs),
+        })
+    }
+
+    /// Arguments of mpv's seek command
+    fn args(self) -> [String; 2] {
+        match self {
+            Seek::Relative(secs) => [secs.to_string(), "relative".to_string()],
+            Seek::Absolute(secs) => [secs.to_string(), "absolute".to_string()],
+            Seek::Percent(percent) => [percent.to_string(), "absolute-percent".to_string()],
+        }
+    }
+}
+
 enum InternalControl {
     Play,
     Pause,
-    Seek(f64),
+    Seek(Seek),
     Open(PathBuf),
+    /// Song to play after the current one without a gap, or None to play nothing after it
+    Preload(Option<PathBuf>),
+    Volume(f64),
+    Mute(bool),
+    /// Volume offset of current song, in dB
+    Gain(f64),
+    /// Whether to use the live loudnorm filter for current song
+    Loudnorm(bool),
+    /// Playback speed, and whether pitch changes with it
+    Speed(f64, bool),
+    /// A and B points to loop between, or None to stop looping
+    AbLoop(Option<(f64, f64)>),
+    /// Switches to an audio device by name, while playing
+    AudioDevice(String),
     Quit,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 #[serde(rename_all = "PascalCase")]
 struct Metadata {
     title: String,
@@ -63,6 +207,27 @@ struct Metadata {
     source: String,
     #[serde(deserialize_with = "vec_to_space_joined")]
     tags: String,
+    // below are filled from .osu files, when available
+    #[serde(default)]
+    creator: String,
+    #[serde(default)]
+    beatmap_set_id: Option<i32>,
+    #[serde(default)]
+    beatmap_ids: Vec<i32>,
+    /// (min, max)
+    #[serde(default)]
+    bpm: Option<(f64, f64)>,
+    /// In milliseconds
+    #[serde(default)]
+    preview_time: Option<u32>,
+    /// In milliseconds, of the longest difficulty
+    #[serde(default)]
+    length: Option<u32>,
+    /// osu! collections containing any difficulty of the set
+    #[serde(default)]
+    collections: Vec<String>,
+    #[serde(default)]
+    difficulties: Vec<beatmap::Difficulty>,
 }
 
 fn vec_to_space_joined<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -74,6 +239,20 @@ where
     Ok(vec.join(" "))
 }
 
+/// How `JsonItem`'s audio_hash and bg_hashes map to file paths
+enum FileLayout {
+    /// osu!lazer's hashed `files/<h>/<hh>/<hash>` layout, under the osu! files directory
+    Hashed(PathBuf),
+    /// Paths relative to a folder, like osu!stable's `Songs/<set folder>/<file>`
+    Relative(PathBuf),
+}
+
+impl Default for FileLayout {
+    fn default() -> Self {
+        FileLayout::Hashed(PathBuf::new())
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct JsonItem {
@@ -82,12 +261,257 @@ struct JsonItem {
     bg_hashes: Vec<String>,
     #[serde(flatten)]
     metadata: Metadata,
+    #[serde(skip)]
+    layout: FileLayout,
+}
+
+impl JsonItem {
+    fn get_file_path(&self, hash: &str) -> PathBuf {
+        match &self.layout {
+            FileLayout::Hashed(osu_path) => get_file_path(osu_path, hash),
+            FileLayout::Relative(base) => base.join(hash),
+        }
+    }
+
+    fn title(&self, unicode: bool) -> String {
+        if !unicode || self.metadata.title_unicode.trim().is_empty() {
+            self.metadata.title.clone()
+        } else {
+            self.metadata.title_unicode.clone()
+        }
+    }
+
+    fn artist(&self, unicode: bool) -> String {
+        if !unicode || self.metadata.artist_unicode.trim().is_empty() {
+            self.metadata.artist.clone()
+        } else {
+            self.metadata.artist_unicode.clone()
+        }
+    }
+
+    /// Entry for playlist files, with the first background as cover
+    fn track(&self, unicode: bool) -> playlist_file::Track {
+        playlist_file::Track {
+            path: self.get_file_path(&self.audio_hash),
+            title: self.title(unicode),
+            artist: self.artist(unicode),
+            length: self.metadata.length.map(|ms| ms as f64 / 1000.0),
+            cover: self.bg_hashes.first().map(|hash| self.get_file_path(hash)),
+        }
+    }
+}
+
+/// Maps files listed in a playlist file back to songs, returning them and the number of unknown files.
+/// Files are matched by path, by file name for hashed files, or by path ending for relative ones.
+fn match_files(json_item: &[JsonItem], paths: &[PathBuf]) -> (Vec<usize>, usize) {
+    let mut by_path: HashMap<PathBuf, usize> = HashMap::new();
+    let mut by_name: HashMap<&OsStr, usize> = HashMap::new();
+    for (i, item) in json_item.iter().enumerate() {
+        by_path
+            .entry(item.get_file_path(&item.audio_hash))
+            .or_insert(i);
+        // a file name alone, for lists from other machines or copies of the files
+        if let Some(name) = Path::new(&item.audio_hash).file_name() {
+            by_name.entry(name).or_insert(i);
+        }
+    }
+    let mut songs = Vec::new();
+    let mut unknown = 0;
+    for path in paths {
+        let found = by_path.get(path).or_else(|| by_name.get(path.file_name()?));
+        match found {
+            Some(&i) => songs.push(i),
+            None => unknown += 1,
+        }
+    }
+    (songs, unknown)
+}
+
+/// Songs matching a query, best matches first
+fn search_songs(
+    json_item: &[JsonItem],
+    ratings: &Ratings,
+    stats: &Stats,
+    expr: &query::Expr,
+) -> Vec<usize> {
+    let mut scored: Vec<(usize, i64)> = json_item
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            expr.score(&query::Song {
+                metadata: &item.metadata,
+                rating: ratings.get(&item.audio_hash),
+                stats: stats.get(&item.audio_hash),
+            })
+            .map(|score| (i, score))
+        })
+        .collect();
+    // stable, so that equal scores keep library order
+    scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    scored.into_iter().map(|(i, _)| i).collect()
+}
+
+impl Metadata {
+    /// Where osu! starts playing in song select, in seconds: the preview point,
+    /// or 40% into the song without one
+    fn preview_start(&self) -> f64 {
+        match (self.preview_time, self.length) {
+            (Some(preview_time), _) => preview_time as f64 / 1000.0,
+            (None, Some(length)) => length as f64 / 1000.0 * 0.4,
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Mapper, BPM, preview point and difficulties, for the main view
+    fn details(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.creator.is_empty() {
+            parts.push(format!("mapped by {}", self.creator));
+        }
+        if let Some(id) = self.beatmap_set_id {
+            parts.push(format!("set #{}", id));
+        }
+        if let Some((min, max)) = self.bpm {
+            if (max - min).abs() < 0.5 {
+                parts.push(format!("{:.0} BPM", min));
+            } else {
+                parts.push(format!("{:.0}-{:.0} BPM", min, max));
+            }
+        }
+        if let Some(preview_time) = self.preview_time {
+            parts.push(format!("preview at {:.1}", preview_time as f64 / 1000.0));
+        }
+        if !self.difficulties.is_empty() {
+            parts.push(
+                self.difficulties
+                    .iter()
+                    .map(|d| {
+                        format!(
+                            "{} (CS{} AR{} OD{} HP{})",
+                            d.name,
+                            d.circle_size,
+                            d.approach_rate,
+                            d.overall_difficulty,
+                            d.hp_drain_rate
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+        parts.join(" | ")
+    }
 }
 
 fn get_file_path(osu_path: &Path, hash: &str) -> PathBuf {
     osu_path.join(&hash[0..1]).join(&hash[0..2]).join(hash)
 }
 
+fn seek(mpv: &Mpv, target: Seek) {
+    let [amount, flags] = target.args();
+    // fails when nothing is playing, like after a file failed to load
+    let _ = mpv.command("seek", &[&amount, &flags]);
+}
+
+/// mpv volume for user volume, song gain (dB) and crossfade factor.
+/// mpv's volume is cubic, so a gain of g dB is a factor of 10^(g/60) on it.
+fn mpv_volume(volume: f64, gain: f64, fade: f64) -> f64 {
+    (volume * 10f64.powf(gain / 60.0) * fade).clamp(0.0, MAX_VOLUME)
+}
+
+enum TailState {
+    Idle,
+    /// Ending song is loaded and paused at the position the crossfade starts from
+    Loaded {
+        start: f64,
+        end: f64,
+        gain: f64,
+    },
+    Playing {
+        end: f64,
+        gain: f64,
+    },
+}
+
+/// Second mpv instance for crossfades. It plays the end of a song, fading out,
+/// while the main instance has already gone on to the next song and fades it in.
+struct CrossfadeTail {
+    mpv: Mpv,
+    crossfade: f64,
+    state: TailState,
+}
+
+impl CrossfadeTail {
+    fn new(crossfade: f64, audio_device: &str, muted: bool) -> libmpv::Result<Self> {
+        let mpv = Mpv::with_initializer(|c| c.set_property("load-scripts", "no"))?;
+        mpv.set_property("vo", "null")?;
+        mpv.set_property("idle", "yes")?;
+        mpv.set_property("audio-device", audio_device)?;
+        mpv.set_property("mute", muted)?;
+        Ok(Self {
+            mpv,
+            crossfade,
+            state: TailState::Idle,
+        })
+    }
+
+    /// Loads the song paused at `start`, to be played up to `end` once the crossfade starts.
+    /// Filter, speed and pitch are those of the main instance.
+    fn load(&mut self, path: &Path, start: f64, end: f64, gain: f64, main: &Mpv) {
+        let _ = self.mpv.set_property("pause", true);
+        let _ = self.mpv.set_property("start", start.to_string());
+        for name in ["af", "speed", "audio-pitch-correction"] {
+            if let Ok(value) = main.get_property::<String>(name) {
+                let _ = self.mpv.set_property(name, value);
+            }
+        }
+        // if it does not load, the tail stays silent and the main instance still goes on
+        let _ = self
+            .mpv
+            .command("loadfile", &[&path.to_string_lossy(), "replace"]);
+        self.state = TailState::Loaded { start, end, gain };
+    }
+
+    fn start(&mut self, volume: f64) {
+        if let TailState::Loaded { end, gain, .. } = self.state {
+            let _ = self
+                .mpv
+                .set_property("volume", mpv_volume(volume, gain, 1.0));
+            let _ = self.mpv.set_property("pause", false);
+            self.state = TailState::Playing { end, gain };
+        }
+    }
+
+    /// Drops a loaded song that is not going to be crossfaded, and leaves a playing one to finish
+    fn unload(&mut self) {
+        if let TailState::Loaded { .. } = self.state {
+            self.stop();
+        }
+    }
+
+    fn stop(&mut self) {
+        let _ = self.mpv.command("stop", &[]);
+        self.state = TailState::Idle;
+    }
+
+    /// Fades out the playing song, stopping at its end
+    fn update(&mut self, volume: f64) {
+        let TailState::Playing { end, gain } = self.state else {
+            return;
+        };
+        match self.mpv.get_property::<f64>("time-pos") {
+            Ok(time) if time < end => {
+                let fade = ((end - time) / self.crossfade).clamp(0.0, 1.0);
+                let _ = self
+                    .mpv
+                    .set_property("volume", mpv_volume(volume, gain, fade));
+            }
+            // past the end, or mpv already finished it
+            _ => self.stop(),
+        }
+    }
+}
+
 fn center_largest_square_crop<I: GenericImageView>(img: &I) -> image::SubImage<&I> {
     let (w, h) = img.dimensions();
     let side_len = w.min(h);
@@ -96,10 +520,105 @@ fn center_largest_square_crop<I: GenericImageView>(img: &I) -> image::SubImage<&
     crop_imm(img, x, y, side_len, side_len)
 }
 
+/// Speed presets mirroring osu! mods
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum SpeedPreset {
+    /// DoubleTime: 1.5x
+    Dt,
+    /// Nightcore: 1.5x, with pitch raised
+    Nc,
+    /// HalfTime: 0.75x
+    Ht,
+}
+
+impl SpeedPreset {
+    fn speed(self) -> f64 {
+        match self {
+            SpeedPreset::Dt | SpeedPreset::Nc => 1.5,
+            SpeedPreset::Ht => 0.75,
+        }
+    }
+
+    fn pitch_shift(self) -> bool {
+        self == SpeedPreset::Nc
+    }
+
+    fn find(speed: f64, pitch_shift: bool) -> Option<Self> {
+        [SpeedPreset::Dt, SpeedPreset::Nc, SpeedPreset::Ht]
+            .into_iter()
+            .find(|p| (p.speed() - speed).abs() < 1e-6 && p.pitch_shift() == pitch_shift)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+enum ReplayGainMode {
+    /// Normalize each song on its own
+    #[default]
+    Track,
+    /// Normalize all songs of a source (album) together, keeping their relative loudness
+    Album,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ShuffleMode {
+    /// Library order
+    Off,
+    /// Shuffle at startup, then keep the same order
+    #[default]
+    Once,
+    /// Shuffle again each time the whole list has been played
+    Reshuffle,
+    /// Pick each next song at random, favouring rarely played songs and songs not played recently
+    Weighted,
+}
+
+impl ShuffleMode {
+    fn next(self) -> Self {
+        match self {
+            ShuffleMode::Off => ShuffleMode::Once,
+            ShuffleMode::Once => ShuffleMode::Reshuffle,
+            ShuffleMode::Reshuffle => ShuffleMode::Weighted,
+            ShuffleMode::Weighted => ShuffleMode::Off,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ShuffleMode::Off => "",
+            ShuffleMode::Once => " shuffle",
+            ShuffleMode::Reshuffle => " reshuffle",
+            ShuffleMode::Weighted => " weighted",
+        }
+    }
+}
+
+/// Relative chance of a song in weighted mode, from its completed plays, how many songs ago it
+/// was last played and its rating. `favourites_only` leaves out the song unless it is a favourite.
+fn weight(plays: u32, ago: Option<usize>, rating: Rating, favourites_only: bool) -> f64 {
+    if favourites_only && !rating.favourite {
+        return 0.0;
+    }
+    // the more often and the more recently played, the less likely
+    let recency = match ago {
+        Some(ago) => (ago as f64 + 1.0) / (HISTORY_LIMIT as f64 + 1.0),
+        None => 1.0,
+    };
+    let liked = if rating.favourite { 2.0 } else { 1.0 } * (1.0 + rating.stars as f64 / 5.0);
+    liked * recency / (1.0 + plays as f64).sqrt()
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum UIState {
     Main,
     Search,
+    Queue,
+    History,
+    Browse,
+    Playlists,
+    Collections,
+    Cues,
+    AudioDevices,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -112,6 +631,10 @@ struct SearchState {
     input: tui_input::Input,
     input_mode: InputMode,
     results: Vec<usize>,
+    /// Last valid query, for highlighting
+    expr: query::Expr,
+    /// Syntax error of the query
+    error: Option<String>,
     list_state: ListState,
     list_height: u16,
 }
@@ -122,12 +645,111 @@ impl Default for SearchState {
             input: tui_input::Input::default(),
             input_mode: InputMode::Editing,
             results: Vec::new(),
+            expr: query::Expr::All,
+            error: None,
             list_state: ListState::default(),
             list_height: 1,
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Grouping {
+    Artist,
+    Source,
+    Tag,
+}
+
+impl Grouping {
+    fn next(self) -> Self {
+        match self {
+            Grouping::Artist => Grouping::Source,
+            Grouping::Source => Grouping::Tag,
+            Grouping::Tag => Grouping::Artist,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Grouping::Artist => "Artists",
+            Grouping::Source => "Sources",
+            Grouping::Tag => "Tags",
+        }
+    }
+}
+
+struct BrowseState {
+    grouping: Grouping,
+    /// Group names and their songs, sorted by name
+    groups: Vec<(String, Vec<usize>)>,
+    group_state: ListState,
+    song_state: ListState,
+    /// Whether the song column (instead of the group column) has focus
+    songs_focused: bool,
+    list_height: u16,
+}
+
+impl Default for BrowseState {
+    fn default() -> Self {
+        Self {
+            grouping: Grouping::Artist,
+            groups: Vec::new(),
+            group_state: ListState::default(),
+            song_state: ListState::default(),
+            songs_focused: false,
+            list_height: 1,
+        }
+    }
+}
+
+/// What the name typed in the playlist picker or cue points view is for
+enum NamePrompt {
+    Create,
+    Rename,
+}
+
+#[derive(Default)]
+struct PlaylistState {
+    list_state: ListState,
+    song_state: ListState,
+    /// Whether the song column (instead of the playlist column) has focus
+    songs_focused: bool,
+    list_height: u16,
+    /// Name being typed for a new or renamed playlist
+    prompt: Option<(NamePrompt, tui_input::Input)>,
+    /// Playlist to delete when d is pressed again
+    deleting: Option<usize>,
+}
+
+#[derive(Default)]
+struct CueState {
+    list_state: ListState,
+    list_height: u16,
+    /// Name being typed for a new or renamed cue point
+    prompt: Option<(NamePrompt, tui_input::Input)>,
+}
+
+struct HistoryEntry {
+    idx: usize,
+    played_at: SystemTime,
+}
+
+fn format_ago(time: SystemTime) -> String {
+    let secs = time.elapsed().unwrap_or_default().as_secs();
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    list_state: ListState,
+    list_height: u16,
+}
+
 fn build_awake() -> Result<KeepAwake, keepawake::Error> {
     keepawake::Builder::default()
         .display(false)
@@ -152,18 +774,93 @@ struct App {
     total: f64,
     paused: bool,
     idx: usize,
+    /// Play order, as indexes into json_item
+    order: Vec<usize>,
+    /// Position of the current song in order
+    pos: usize,
+    shuffle: ShuffleMode,
+    rng: StdRng,
     title: String,
     artist: String,
     source: String,
+    details: String,
+    /// Statistics of current song, before this play
+    song_stats: String,
     cover_path: Option<PathBuf>,
     is_unicode: bool,
     bg_img: StatefulProtocol,
-    osu_path: PathBuf,
     json_item: Vec<JsonItem>,
     controls: MediaControls,
     xdg_dirs: xdg::BaseDirectories,
     ui_state: UIState,
     search_state: SearchState,
+    /// Indexes into json_item to play before continuing in library order
+    queue: VecDeque<usize>,
+    queue_state: QueueState,
+    /// Songs actually played, the last one is the current song
+    history: VecDeque<HistoryEntry>,
+    /// Songs gone back from with previous, which next plays again before the queue. Most recent last.
+    forward: Vec<usize>,
+    history_state: QueueState,
+    browse_state: BrowseState,
+    /// User playlists, sorted by name
+    playlists: Vec<Playlist>,
+    playlist_state: PlaylistState,
+    /// Name of the playlist being played, or None for the whole library
+    playlist: Option<String>,
+    /// osu! collections and their song counts, sorted by name
+    collections: Vec<(String, usize)>,
+    collection_state: QueueState,
+    /// Name of the collection being played, when not playing a playlist
+    collection: Option<String>,
+    /// Name of the playlist songs are added to from search and library browser
+    target_playlist: Option<String>,
+    /// Indexes into json_item by audio file key
+    by_hash: HashMap<String, usize>,
+    /// Feedback of the last action, shown until next key press
+    message: String,
+    /// Timestamp being typed to jump to
+    seek_prompt: Option<tui_input::Input>,
+    /// Name of the audio device in use, "auto" being mpv's default
+    audio_device: String,
+    audio_devices: Vec<AudioDevice>,
+    audio_device_state: QueueState,
+    stats: Stats,
+    ratings: Ratings,
+    gains: Gains,
+    config: Config,
+    cues: Cues,
+    cue_state: CueState,
+    /// A point, and B point once set; mpv loops only when both are set
+    ab_loop: Option<(f64, Option<f64>)>,
+    speed: f64,
+    /// Pitch changes with speed, like Nightcore (no pitch correction)
+    pitch_shift: bool,
+    /// Normalize loudness, with a static gain for analyzed songs
+    loudnorm: bool,
+    replaygain: ReplayGainMode,
+    loudness: Loudness,
+    /// Combined loudness of analyzed songs of each source
+    source_loudness: HashMap<String, loudness::Analysis>,
+    /// Only play favourite songs after the queue
+    favourites_only: bool,
+    /// Song being listened to, and seconds listened
+    listening: Option<(usize, f64)>,
+    /// Start each song at its preview point
+    start_at_preview: bool,
+    /// Only play `preview_length` seconds from the preview point of each song
+    previewing: bool,
+    preview_length: f64,
+    /// When to go to the next song in preview mode, known once the song is loaded
+    preview_end: Option<f64>,
+    /// Search results left to audition in preview mode, played before the queue
+    previews: VecDeque<usize>,
+    /// Preload the next song into mpv, for gapless playback
+    gapless: bool,
+    /// Song preloaded into mpv
+    preloaded: Option<usize>,
+    /// Song picked ahead in weighted mode, so that it could be preloaded
+    weighted_next: Option<usize>,
     repeat: bool,
     awake: Option<KeepAwake>,
     ui_dirty: bool,
@@ -183,107 +880,457 @@ impl App {
     fn new(
         picker: &ratatui_image::picker::Picker,
         controls: MediaControls,
-        osu_path: &Path,
         json_item: Vec<JsonItem>,
         xdg_dirs: xdg::BaseDirectories,
+        shuffle: ShuffleMode,
+        rng: StdRng,
     ) -> Self {
-        App {
+        let stats: Stats = store::load_json(&xdg_dirs.get_data_home().join("stats.json"));
+        let ratings: Ratings = store::load_json(&xdg_dirs.get_data_home().join("ratings.json"));
+        let gains: Gains = store::load_json(&xdg_dirs.get_data_home().join("gains.json"));
+        let cues: Cues = store::load_json(&xdg_dirs.get_data_home().join("cues.json"));
+        let playlists = playlists::load_all(&xdg_dirs.get_data_home().join("playlists"));
+        let by_hash = json_item
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.audio_hash.clone(), i))
+            .collect();
+        let mut collections: HashMap<String, usize> = HashMap::new();
+        for name in json_item.iter().flat_map(|item| &item.metadata.collections) {
+            *collections.entry(name.clone()).or_default() += 1;
+        }
+        let mut collections: Vec<(String, usize)> = collections.into_iter().collect();
+        collections.sort_by_cached_key(|(name, _)| name.to_lowercase());
+        let loudness: Loudness = store::load_json(&xdg_dirs.get_cache_home().join("loudness.json"));
+        let mut by_source: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for item in &json_item {
+            if !item.metadata.source.is_empty() && loudness.get(&item.audio_hash).is_some() {
+                by_source
+                    .entry(&item.metadata.source)
+                    .or_default()
+                    .insert(&item.audio_hash);
+            }
+        }
+        let source_loudness = by_source
+            .into_iter()
+            .filter_map(|(source, keys)| {
+                let analysis =
+                    loudness::Analysis::combine(keys.into_iter().filter_map(|k| loudness.get(k)))?;
+                Some((source.to_string(), analysis))
+            })
+            .collect();
+        let mut app = App {
             progress: 0.0,
             total: 0.0,
             paused: false,
             idx: 0,
+            order: (0..json_item.len()).collect(),
+            pos: 0,
+            shuffle,
+            rng,
             title: String::new(),
             artist: String::new(),
             source: String::new(),
+            details: String::new(),
+            song_stats: String::new(),
             cover_path: None,
             is_unicode: false,
             bg_img: picker.new_resize_protocol(empty_image()),
-            osu_path: osu_path.to_path_buf(),
             json_item,
             controls,
             xdg_dirs,
             ui_state: UIState::Main,
             search_state: SearchState::default(),
+            queue: VecDeque::new(),
+            queue_state: QueueState::default(),
+            history: VecDeque::new(),
+            forward: Vec::new(),
+            history_state: QueueState::default(),
+            browse_state: BrowseState::default(),
+            playlists,
+            playlist_state: PlaylistState::default(),
+            playlist: None,
+            collections,
+            collection_state: QueueState::default(),
+            collection: None,
+            target_playlist: None,
+            by_hash,
+            message: String::new(),
+            seek_prompt: None,
+            audio_device: "auto".to_string(),
+            audio_devices: Vec::new(),
+            audio_device_state: QueueState::default(),
+            stats,
+            ratings,
+            gains,
+            config: Config::default(),
+            cues,
+            cue_state: CueState::default(),
+            ab_loop: None,
+            speed: 1.0,
+            pitch_shift: false,
+            loudnorm: false,
+            replaygain: ReplayGainMode::default(),
+            loudness,
+            source_loudness,
+            favourites_only: false,
+            listening: None,
+            start_at_preview: false,
+            previewing: false,
+            preview_length: DEFAULT_PREVIEW_LENGTH,
+            preview_end: None,
+            previews: VecDeque::new(),
+            gapless: false,
+            preloaded: None,
+            weighted_next: None,
             repeat: false,
             awake: build_awake_anyway(),
             ui_dirty: true,
+        };
+        app.reorder();
+        if shuffle == ShuffleMode::Weighted {
+            app.idx = app.pick_weighted();
+            app.sync_pos();
+        } else {
+            app.idx = app.order[0];
         }
+        app
     }
 
-    fn open(&self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+    fn open(&mut self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        let item = &get_current_item!(self);
         mpv_control_tx
-            .send(InternalControl::Open(get_file_path(
-                &self.osu_path,
-                &get_current_item!(self).audio_hash,
-            )))
+            .send(InternalControl::Open(item.get_file_path(&item.audio_hash)))
             .unwrap();
+        // opening replaces mpv's playlist
+        self.preloaded = None;
+        self.started(mpv_control_tx);
     }
 
-    fn get_title(&self, item: &JsonItem) -> String {
-        if !self.is_unicode {
-            item.metadata.title.clone()
-        } else {
-            let u = item.metadata.title_unicode.clone();
-            if u.trim().is_empty() {
-                item.metadata.title.clone()
-            } else {
-                u
+    /// Bookkeeping for current song, once mpv is told to play it
+    fn started(&mut self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        // going back in history reopens its last entry, which should not be recorded again
+        if self.history.back().map(|e| e.idx) != Some(self.idx) {
+            self.history.push_back(HistoryEntry {
+                idx: self.idx,
+                played_at: SystemTime::now(),
+            });
+            if self.history.len() > HISTORY_LIMIT {
+                self.history.pop_front();
+            }
+        }
+        self.finish(End::Switched);
+        let item = &get_current_item!(self);
+        self.song_stats = match self.stats.get(&item.audio_hash) {
+            Some(song) => format!(
+                "played {} times before ({} completed, {} skipped, {} listened){}",
+                song.plays,
+                song.completed,
+                song.skips,
+                stats::format_duration(song.listen_time),
+                song.last_played()
+                    .map(|t| format!(", last {}", format_ago(t)))
+                    .unwrap_or_default()
+            ),
+            None => "first play".to_string(),
+        };
+        self.stats.start(
+            &item.audio_hash,
+            &item.metadata.title,
+            &item.metadata.artist,
+            &item.metadata.source,
+        );
+        self.listening = Some((self.idx, 0.0));
+        self.send_gain(mpv_control_tx.clone());
+        // loop points and selected cue are of the previous song
+        if self.ab_loop.take().is_some() {
+            mpv_control_tx.send(InternalControl::AbLoop(None)).unwrap();
+        }
+        self.cue_state.list_state.select(None);
+        self.preview_end = None;
+        if self.start_at_preview || self.previewing {
+            let start = item.metadata.preview_start();
+            if start > 0.0 {
+                mpv_control_tx
+                    .send(InternalControl::Seek(Seek::Absolute(start)))
+                    .unwrap();
             }
         }
     }
 
-    fn get_artist(&self, item: &JsonItem) -> String {
-        if !self.is_unicode {
-            item.metadata.artist.clone()
-        } else {
-            let u = item.metadata.artist_unicode.clone();
-            if u.trim().is_empty() {
-                item.metadata.artist.clone()
-            } else {
-                u
+    /// Song next_idx would pick, without picking it.
+    /// None when it cannot be known, like before reshuffling or in preview mode.
+    fn peek_next(&mut self) -> Option<usize> {
+        if self.repeat {
+            return Some(self.idx);
+        }
+        if self.previewing {
+            return None;
+        }
+        if let Some(&idx) = self.forward.last() {
+            return Some(idx);
+        }
+        if let Some(&idx) = self.queue.front() {
+            return Some(idx);
+        }
+        if self.shuffle == ShuffleMode::Weighted {
+            if self.weighted_next.is_none() {
+                self.weighted_next = Some(self.pick_weighted());
             }
+            return self.weighted_next;
         }
+        let favourites_only = self.only_favourites();
+        let len = self.order.len();
+        (self.pos + 1..=self.pos + len)
+            .take_while(|&p| p < len || self.shuffle != ShuffleMode::Reshuffle)
+            .map(|p| self.order[p % len])
+            .find(|&i| !favourites_only || self.is_favourite(i))
+    }
+
+    /// Tells mpv the song to play next, when it changes
+    fn sync_preload(&mut self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        if !self.gapless {
+            return;
+        }
+        let next = self.peek_next();
+        if next != self.preloaded {
+            self.preloaded = next;
+            let path = next.map(|i| {
+                let item = &self.json_item[i];
+                item.get_file_path(&item.audio_hash)
+            });
+            mpv_control_tx.send(InternalControl::Preload(path)).unwrap();
+        }
+    }
+
+    /// Whether the preview of current song has been played, in preview mode
+    fn preview_over(&self) -> bool {
+        self.previewing && self.preview_end.is_some_and(|end| self.progress >= end)
+    }
+
+    /// Records how listening to current song ended
+    fn finish(&mut self, end: End) {
+        if let Some((idx, listened)) = self.listening.take() {
+            self.stats
+                .finish(&self.json_item[idx].audio_hash, listened, end);
+        }
+    }
+
+    fn save_stats(&self) {
+        store::save_placed(
+            "stats",
+            self.xdg_dirs.place_data_file("stats.json"),
+            |path| store::save_json(path, &self.stats),
+        );
+    }
+
+    fn get_title(&self, item: &JsonItem) -> String {
+        item.title(self.is_unicode)
+    }
+
+    fn get_artist(&self, item: &JsonItem) -> String {
+        item.artist(self.is_unicode)
     }
 
     fn construct_terminal_title(&self) -> String {
+        let speed = self.speed_label();
         format!(
-            "{} - {} {}",
+            "{} - {}{} {}",
             APP_DISPLAY_NAME,
             if self.paused { "Paused" } else { "Playing" },
+            if speed.is_empty() {
+                speed
+            } else {
+                format!(" [{}]", speed)
+            },
             self.get_title(&get_current_item!(self))
         )
     }
 
+    fn set_speed(
+        &mut self,
+        speed: f64,
+        pitch_shift: bool,
+        mpv_control_tx: mpsc::Sender<InternalControl>,
+    ) {
+        // round away float error from repeated steps
+        self.speed = ((speed * 100.0).round() / 100.0).clamp(MIN_SPEED, MAX_SPEED);
+        self.pitch_shift = pitch_shift;
+        mpv_control_tx
+            .send(InternalControl::Speed(self.speed, self.pitch_shift))
+            .unwrap();
+        set_terminal_title(&self.construct_terminal_title());
+    }
+
+    /// Switches to a preset, or back to normal speed if it is already on
+    fn toggle_preset(
+        &mut self,
+        preset: SpeedPreset,
+        mpv_control_tx: mpsc::Sender<InternalControl>,
+    ) {
+        if SpeedPreset::find(self.speed, self.pitch_shift) == Some(preset) {
+            self.set_speed(1.0, false, mpv_control_tx);
+        } else {
+            self.set_speed(preset.speed(), preset.pitch_shift(), mpv_control_tx);
+        }
+    }
+
+    /// Switches audio device, and remembers it for the next runs
+    fn set_audio_device(&mut self, name: &str, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        mpv_control_tx
+            .send(InternalControl::AudioDevice(name.to_string()))
+            .unwrap();
+        self.remember_audio_device(name);
+    }
+
+    fn remember_audio_device(&mut self, name: &str) {
+        self.audio_device = name.to_string();
+        self.config.audio_device = (name != "auto").then(|| name.to_string());
+        self.save_config();
+    }
+
+    /// Shows the device picker again with the error, as mpv stayed on `current`
+    fn audio_device_failed(&mut self, current: &str, error: String) {
+        self.remember_audio_device(current);
+        self.message = error;
+        self.ui_state = UIState::AudioDevices;
+        self.ui_dirty = true;
+    }
+
+    /// Seek step for left/right, smaller with shift and larger with ctrl
+    fn seek_step(&self, modifiers: event::KeyModifiers) -> f64 {
+        if modifiers.contains(event::KeyModifiers::CONTROL) {
+            self.config.large_seek_step
+        } else if modifiers.contains(event::KeyModifiers::SHIFT) {
+            self.config.small_seek_step
+        } else {
+            self.config.seek_step
+        }
+    }
+
+    /// Sets A, then B, then stops looping, like mpv's l key
+    fn cycle_ab_loop(&mut self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        self.ab_loop = match self.ab_loop {
+            None => Some((self.progress, None)),
+            Some((a, None)) if self.progress > a => {
+                mpv_control_tx
+                    .send(InternalControl::AbLoop(Some((a, self.progress))))
+                    .unwrap();
+                Some((a, Some(self.progress)))
+            }
+            // B before A starts over from there
+            Some((_, None)) => Some((self.progress, None)),
+            Some((_, Some(_))) => {
+                mpv_control_tx.send(InternalControl::AbLoop(None)).unwrap();
+                None
+            }
+        };
+    }
+
+    fn ab_loop_label(&self) -> String {
+        match self.ab_loop {
+            None => String::new(),
+            Some((a, None)) => format!(" A {:.1}", a),
+            Some((a, Some(b))) => format!(" loop {:.1}-{:.1}", a, b),
+        }
+    }
+
+    fn save_cues(&self) {
+        store::save_placed("cues", self.xdg_dirs.place_data_file("cues.json"), |path| {
+            store::save_json(path, &self.cues)
+        });
+    }
+
+    /// Adds a cue point at the current position, returning its index
+    fn add_cue(&mut self, name: &str) -> usize {
+        let key = get_current_item!(self).audio_hash.clone();
+        let name = match name.trim() {
+            "" => format!("Cue {}", self.cues.get(&key).len() + 1),
+            name => name.to_string(),
+        };
+        let i = self.cues.add(
+            &key,
+            Cue {
+                name,
+                time: self.progress,
+            },
+        );
+        self.save_cues();
+        i
+    }
+
+    fn rename_cue(&mut self, i: usize, name: &str) {
+        let key = get_current_item!(self).audio_hash.clone();
+        if !name.trim().is_empty() {
+            self.cues.rename(&key, i, name.trim());
+            self.save_cues();
+        }
+    }
+
+    fn delete_cue(&mut self, i: usize) {
+        let key = get_current_item!(self).audio_hash.clone();
+        self.cues.remove(&key, i);
+        self.save_cues();
+    }
+
+    /// Seeks to the current song's cue point `i`, if there is one
+    fn jump_to_cue(&self, i: usize, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        if let Some(cue) = self.cues.get(&get_current_item!(self).audio_hash).get(i) {
+            mpv_control_tx
+                .send(InternalControl::Seek(Seek::Absolute(cue.time)))
+                .unwrap();
+        }
+    }
+
+    /// Like "NC" or "1.25x pitch", empty at normal speed
+    fn speed_label(&self) -> String {
+        match SpeedPreset::find(self.speed, self.pitch_shift) {
+            Some(preset) => format!("{:?}", preset).to_uppercase(),
+            None if self.speed == 1.0 && !self.pitch_shift => String::new(),
+            None => format!(
+                "{:.2}x{}",
+                self.speed,
+                if self.pitch_shift { " pitch" } else { "" }
+            ),
+        }
+    }
+
     fn update_metadata(&mut self, mut picker: Option<&ratatui_image::picker::Picker>) {
         let item = &get_current_item!(self);
         self.title = self.get_title(item);
         self.artist = self.get_artist(item);
         self.source.clone_from(&item.metadata.source);
+        self.details = item.metadata.details();
 
         if let Some(picker) = picker.as_mut() {
-            let bg_hashes = &get_current_item!(self).bg_hashes;
-            // randomly choose one
-            let bg_hash = bg_hashes.choose(&mut rand::thread_rng());
-            match bg_hash {
-                Some(bg_hash) => {
-                    let image = image::ImageReader::open(get_file_path(&self.osu_path, bg_hash))
-                        .unwrap()
+            let item = &get_current_item!(self);
+            // randomly choose one, and show none if it cannot be read
+            let bg = item
+                .bg_hashes
+                .choose(&mut rand::thread_rng())
+                .and_then(|bg_hash| {
+                    let image = image::ImageReader::open(item.get_file_path(bg_hash))
+                        .ok()?
                         .with_guessed_format()
-                        .unwrap()
+                        .ok()?
                         .decode()
-                        .unwrap()
-                        .to_rgb8();
+                        .ok()?;
+                    Some((bg_hash, image.to_rgb8()))
+                });
+            match bg {
+                Some((bg_hash, image)) => {
                     // check if we shall generate a cover...
-                    let cache_filename = format!("{}.cover.jpg", bg_hash);
-                    let cache_path = self.xdg_dirs.place_cache_file(cache_filename).unwrap();
-                    if !cache_path.exists() {
-                        let cover = center_largest_square_crop(&image);
-                        cover
-                            .to_image()
-                            .save_with_format(cache_path.clone(), image::ImageFormat::Jpeg)
-                            .unwrap();
-                    }
-                    self.cover_path = Some(cache_path);
+                    // (stable's bg "hash" is a relative path, so flatten it)
+                    let cache_filename = format!("{}.cover.jpg", bg_hash.replace('/', "_"));
+                    // the cover is only for media controls, which can do without it
+                    let cache_path = self.xdg_dirs.place_cache_file(cache_filename).ok();
+                    self.cover_path = cache_path.filter(|cache_path| {
+                        cache_path.exists()
+                            || center_largest_square_crop(&image)
+                                .to_image()
+                                .save_with_format(cache_path, image::ImageFormat::Jpeg)
+                                .is_ok()
+                    });
 
                     self.bg_img = picker.new_resize_protocol(image::DynamicImage::ImageRgb8(image));
                 }
@@ -297,6 +1344,15 @@ impl App {
     }
 
     fn update_progress(&mut self, progress: f64) {
+        // count only normal playback, not seeking
+        let delta = progress - self.progress;
+        if let Some((_, listened)) = self.listening.as_mut()
+            && !self.paused
+            && delta > 0.0
+            && delta < 1.0
+        {
+            *listened += delta;
+        }
         self.progress = progress;
         self.set_playback();
     }
@@ -324,6 +1380,15 @@ impl App {
 
     fn update_duration(&mut self, total: f64) {
         self.total = total;
+        // duration changes when a new song is loaded, so the position is now of this song
+        if self.previewing && total > 0.0 {
+            let start = get_current_item!(self).metadata.preview_start();
+            self.preview_end = Some(start + self.preview_length);
+        }
+        if total > 0.0 {
+            self.stats
+                .set_length(&get_current_item!(self).audio_hash, total);
+        }
         self.set_metadata();
     }
 
@@ -339,23 +1404,364 @@ impl App {
                     .as_ref()
                     .map(|p| Url::from_file_path(p).unwrap().to_string())
                     .as_deref(),
+                user_rating: self
+                    .ratings
+                    .get(&get_current_item!(self).audio_hash)
+                    .normalized(),
             })
             .expect("Cannot set metadata, is there another instance running?");
         set_terminal_title(&self.construct_terminal_title());
     }
 
+    /// Rebuilds the play order for the current shuffle mode, starting with the current song
+    fn reorder(&mut self) {
+        self.order = self.pool();
+        self.weighted_next = None;
+        if matches!(self.shuffle, ShuffleMode::Once | ShuffleMode::Reshuffle) {
+            self.order.shuffle(&mut self.rng);
+            if let Some(pos) = self.order.iter().position(|&i| i == self.idx) {
+                self.order.swap(0, pos);
+            }
+        }
+        self.sync_pos();
+    }
+
+    /// Songs of a playlist that are in the library, in playlist order
+    fn playlist_songs(&self, playlist: &Playlist) -> Vec<usize> {
+        let mut songs: Vec<usize> = Vec::with_capacity(playlist.songs.len());
+        for &i in playlist.songs.iter().filter_map(|k| self.by_hash.get(k)) {
+            if !songs.contains(&i) {
+                songs.push(i);
+            }
+        }
+        songs
+    }
+
+    /// Songs to play from: the playing playlist or collection, or the whole library
+    fn pool(&self) -> Vec<usize> {
+        let songs = if let Some(playlist) = self
+            .playlist
+            .as_ref()
+            .and_then(|name| self.playlists.iter().find(|p| &p.name == name))
+        {
+            self.playlist_songs(playlist)
+        } else if let Some(name) = &self.collection {
+            (0..self.json_item.len())
+                .filter(|&i| self.json_item[i].metadata.collections.contains(name))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if songs.is_empty() {
+            (0..self.json_item.len()).collect()
+        } else {
+            songs
+        }
+    }
+
+    fn sync_pos(&mut self) {
+        self.pos = self.order.iter().position(|&i| i == self.idx).unwrap_or(0);
+    }
+
+    fn set_shuffle(&mut self, shuffle: ShuffleMode) {
+        self.shuffle = shuffle;
+        self.reorder();
+        self.sync_order_controls();
+    }
+
+    fn toggle_shuffle(&mut self) {
+        self.set_shuffle(self.shuffle.next());
+    }
+
+    fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = repeat;
+        self.sync_order_controls();
+    }
+
+    /// Tells MPRIS clients the shuffle and loop status.
+    /// The list always wraps around, so without repeat it loops the playlist.
+    fn sync_order_controls(&mut self) {
+        let _ = self.controls.set_shuffle(self.shuffle != ShuffleMode::Off);
+        let _ = self.controls.set_loop_status(if self.repeat {
+            LoopStatus::Track
+        } else {
+            LoopStatus::Playlist
+        });
+    }
+
+    /// Whether favourites only mode leaves out songs. Without any favourite to play, it plays everything.
+    fn only_favourites(&self) -> bool {
+        self.favourites_only && self.order.iter().any(|&i| self.is_favourite(i))
+    }
+
+    fn pick_weighted(&mut self) -> usize {
+        let favourites_only = self.only_favourites();
+        let weights: Vec<f64> = self
+            .order
+            .iter()
+            .map(|&i| {
+                let key = &self.json_item[i].audio_hash;
+                let plays = self.stats.get(key).map_or(0, |song| song.completed);
+                let ago = self.history.iter().rev().position(|e| e.idx == i);
+                weight(plays, ago, self.ratings.get(key), favourites_only)
+            })
+            .collect();
+        match WeightedIndex::new(&weights) {
+            Ok(dist) => self.order[dist.sample(&mut self.rng)],
+            // an empty play order
+            Err(_) => *self.order.choose(&mut self.rng).unwrap_or(&0),
+        }
+    }
+
     fn next_idx(&mut self) {
-        self.idx += 1;
-        if self.idx >= self.json_item.len() {
-            self.idx = 0;
+        if let Some(idx) = self.forward.pop() {
+            self.idx = idx;
+            return;
+        }
+        if self.previewing
+            && let Some(idx) = self.previews.pop_front()
+        {
+            self.idx = idx;
+            return;
+        }
+        if let Some(idx) = self.queue.pop_front() {
+            self.idx = idx;
+            return;
+        }
+        if self.shuffle == ShuffleMode::Weighted {
+            self.idx = match self.weighted_next.take() {
+                Some(idx) => idx,
+                None => self.pick_weighted(),
+            };
+            self.sync_pos();
+            return;
+        }
+        let favourites_only = self.only_favourites();
+        loop {
+            self.pos += 1;
+            if self.pos >= self.order.len() {
+                self.pos = 0;
+                if self.shuffle == ShuffleMode::Reshuffle {
+                    self.order.shuffle(&mut self.rng);
+                }
+            }
+            self.idx = self.order[self.pos];
+            if !favourites_only || self.is_favourite(self.idx) {
+                break;
+            }
         }
     }
 
+    fn is_favourite(&self, idx: usize) -> bool {
+        self.ratings.get(&self.json_item[idx].audio_hash).favourite
+    }
+
+    fn save_ratings(&self) {
+        store::save_placed(
+            "ratings",
+            self.xdg_dirs.place_data_file("ratings.json"),
+            |path| store::save_json(path, &self.ratings),
+        );
+    }
+
+    fn save_config(&self) {
+        store::save_placed(
+            "config",
+            self.xdg_dirs.place_config_file("config.json"),
+            |path| store::save_json_pretty(path, &self.config),
+        );
+    }
+
+    fn set_volume(&mut self, volume: f64, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        self.config.volume = volume.clamp(0.0, MAX_VOLUME);
+        // changing volume unmutes, like in most players
+        if self.config.muted {
+            self.config.muted = false;
+            mpv_control_tx.send(InternalControl::Mute(false)).unwrap();
+        }
+        mpv_control_tx
+            .send(InternalControl::Volume(self.config.volume))
+            .unwrap();
+        let _ = self.controls.set_volume(self.config.volume / 100.0);
+        self.save_config();
+    }
+
+    fn toggle_mute(&mut self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        self.config.muted = !self.config.muted;
+        mpv_control_tx
+            .send(InternalControl::Mute(self.config.muted))
+            .unwrap();
+        self.save_config();
+    }
+
+    /// Static gain of current song from loudness analysis, or None if it is not analyzed
+    fn loudness_gain(&self) -> Option<f64> {
+        let item = &get_current_item!(self);
+        let analysis = match self.replaygain {
+            ReplayGainMode::Album => self.source_loudness.get(&item.metadata.source),
+            ReplayGainMode::Track => None,
+        };
+        analysis
+            .or_else(|| self.loudness.get(&item.audio_hash))
+            .map(loudness::Analysis::gain)
+    }
+
+    /// Sends the gain of current song, and whether it needs live loudnorm
+    fn send_gain(&self, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        let mut gain = self.gains.get(&get_current_item!(self).audio_hash);
+        if self.loudnorm {
+            let loudness_gain = self.loudness_gain();
+            mpv_control_tx
+                .send(InternalControl::Loudnorm(loudness_gain.is_none()))
+                .unwrap();
+            gain += loudness_gain.unwrap_or_default();
+        }
+        mpv_control_tx.send(InternalControl::Gain(gain)).unwrap();
+    }
+
+    /// Changes volume offset of current song
+    fn add_gain(&mut self, delta: f64, mpv_control_tx: mpsc::Sender<InternalControl>) {
+        let key = get_current_item!(self).audio_hash.clone();
+        self.gains.add(&key, delta);
+        self.send_gain(mpv_control_tx);
+        store::save_placed(
+            "gains",
+            self.xdg_dirs.place_data_file("gains.json"),
+            |path| store::save_json(path, &self.gains),
+        );
+    }
+
+    /// Volume, mute and gain of current song, for the main view
+    fn volume_label(&self) -> String {
+        let mut label = if self.config.muted {
+            "muted".to_string()
+        } else {
+            format!("vol {:.0}%", self.config.volume)
+        };
+        let gain = self.gains.get(&get_current_item!(self).audio_hash);
+        if gain != 0.0 {
+            label.push_str(&format!(" {:+.1} dB", gain));
+        }
+        label
+    }
+
+    /// Handles rating keys for song `idx`, returns whether the key is handled
+    fn rate(&mut self, idx: usize, code: event::KeyCode) -> bool {
+        let key = &self.json_item[idx].audio_hash;
+        match code {
+            event::KeyCode::Char('f') => self.ratings.toggle_favourite(key),
+            event::KeyCode::Char('+') | event::KeyCode::Char('=') => self.ratings.add_stars(key, 1),
+            event::KeyCode::Char('-') => self.ratings.add_stars(key, -1),
+            _ => return false,
+        }
+        self.save_ratings();
+        if idx == self.idx {
+            self.set_metadata();
+        }
+        true
+    }
+
     fn prev_idx(&mut self) {
-        if self.idx == 0 {
-            self.idx = self.json_item.len() - 1;
+        if self.history.len() >= 2 {
+            // so that next goes forward again
+            let current = self.history.pop_back().unwrap();
+            self.forward.push(current.idx);
+            self.idx = self.history.back().unwrap().idx;
+            return;
+        }
+        if self.pos == 0 {
+            self.pos = self.order.len() - 1;
         } else {
-            self.idx -= 1;
+            self.pos -= 1;
+        }
+        self.idx = self.order[self.pos];
+    }
+
+    fn play_idx(
+        &mut self,
+        idx: usize,
+        mpv_control_tx: mpsc::Sender<InternalControl>,
+        picker: &Picker,
+    ) {
+        self.idx = idx;
+        // continue in play order from here
+        self.sync_pos();
+        self.forward.clear();
+        self.previews.clear();
+        self.open(mpv_control_tx.clone());
+        self.update_metadata(Some(picker));
+        self.set_paused(false, mpv_control_tx);
+    }
+
+    fn session(&self) -> Session {
+        let key = |idx: usize| self.json_item[idx].audio_hash.clone();
+        Session {
+            current: key(self.idx),
+            position: self.progress,
+            queue: self.queue.iter().map(|&i| key(i)).collect(),
+            order: self.order.iter().map(|&i| key(i)).collect(),
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+            unicode: self.is_unicode,
+            favourites_only: self.favourites_only,
+            start_at_preview: self.start_at_preview,
+            previewing: self.previewing,
+            playlist: self.playlist.clone(),
+            collection: self.collection.clone(),
+        }
+    }
+
+    fn save_session(&self) {
+        store::save_placed(
+            "session",
+            self.xdg_dirs.place_state_file("session.json"),
+            |path| store::save_json(path, &self.session()),
+        );
+    }
+
+    /// Restores a saved session, returning the playback position to seek to
+    fn restore(&mut self, session: Session) -> f64 {
+        self.playlist = session.playlist;
+        self.collection = session.collection;
+        let pool = self.pool();
+        // songs not in the pool count as seen, so that they are left out
+        let mut seen = vec![true; self.json_item.len()];
+        for &i in &pool {
+            seen[i] = false;
+        }
+        let mut order = Vec::with_capacity(pool.len());
+        for &i in session.order.iter().filter_map(|k| self.by_hash.get(k)) {
+            if !seen[i] {
+                seen[i] = true;
+                order.push(i);
+            }
+        }
+        // songs added to the library or playlist since then go last
+        order.extend(pool.into_iter().filter(|&i| !seen[i]));
+        self.queue = session
+            .queue
+            .iter()
+            .filter_map(|k| self.by_hash.get(k).copied())
+            .collect();
+        let current = self.by_hash.get(&session.current).copied();
+        self.order = order;
+        self.shuffle = session.shuffle;
+        self.repeat = session.repeat;
+        self.is_unicode = session.unicode;
+        self.favourites_only = session.favourites_only;
+        self.start_at_preview = session.start_at_preview;
+        self.previewing = session.previewing;
+        match current {
+            Some(idx) => {
+                self.idx = idx;
+                self.sync_pos();
+                session.position
+            }
+            None => {
+                self.pos = 0;
+                self.idx = self.order[0];
+                0.0
+            }
         }
     }
 
@@ -378,26 +1784,340 @@ impl App {
         self.set_playback();
     }
 
-    fn search(&self, query: &str) -> Vec<usize> {
-        let mut result = Vec::new();
-        let query = query.to_lowercase();
+    /// Returns matching songs, best matches first
+    fn search(&self, expr: &query::Expr) -> Vec<usize> {
+        search_songs(&self.json_item, &self.ratings, &self.stats, expr)
+    }
+
+    /// Runs the query in search box, keeping previous results on syntax error
+    fn update_search(&mut self) {
+        match query::parse(self.search_state.input.value()) {
+            Ok(expr) => {
+                self.search_state.results = self.search(&expr);
+                self.search_state.error = None;
+                self.search_state.expr = expr;
+                self.search_state
+                    .list_state
+                    .select(if self.search_state.results.is_empty() {
+                        None
+                    } else {
+                        Some(0)
+                    });
+            }
+            Err(e) => {
+                self.search_state.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Groups songs for the library browser, by current grouping
+    fn build_groups(&mut self) {
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
         for (i, item) in self.json_item.iter().enumerate() {
-            if item.metadata.title.to_ascii_lowercase().contains(&query)
-                || item.metadata.artist.to_ascii_lowercase().contains(&query)
-                || item.metadata.source.to_lowercase().contains(&query)
-                || item.metadata.title_unicode.to_lowercase().contains(&query)
-                || item.metadata.artist_unicode.to_lowercase().contains(&query)
-                || item.metadata.tags.to_lowercase().contains(&query)
-            {
-                result.push(i);
+            let names = match self.browse_state.grouping {
+                Grouping::Artist => vec![self.get_artist(item)],
+                Grouping::Source => vec![item.metadata.source.clone()],
+                Grouping::Tag => {
+                    let mut tags: Vec<String> = item
+                        .metadata
+                        .tags
+                        .split_whitespace()
+                        .map(|t| t.to_lowercase())
+                        .collect();
+                    tags.sort();
+                    tags.dedup();
+                    tags
+                }
+            };
+            for name in names {
+                let name = if name.trim().is_empty() {
+                    "(none)".to_string()
+                } else {
+                    name
+                };
+                groups.entry(name).or_default().push(i);
             }
         }
-        result
+        let mut groups: Vec<(String, Vec<usize>)> = groups.into_iter().collect();
+        groups.sort_by_cached_key(|(name, _)| name.to_lowercase());
+        let state = &mut self.browse_state;
+        state.groups = groups;
+        state
+            .group_state
+            .select((!state.groups.is_empty()).then_some(0));
+        state.song_state.select(None);
+        state.songs_focused = false;
+    }
+
+    /// Plays songs now, with all but the first put in front of the queue
+    fn play_group(
+        &mut self,
+        songs: &[usize],
+        mpv_control_tx: mpsc::Sender<InternalControl>,
+        picker: &Picker,
+    ) {
+        let Some((&first, rest)) = songs.split_first() else {
+            return;
+        };
+        for &i in rest.iter().rev() {
+            self.queue.push_front(i);
+        }
+        self.play_idx(first, mpv_control_tx, picker);
+    }
+
+    fn save_playlist(&mut self, i: usize) {
+        let result = self
+            .xdg_dirs
+            .create_data_directory("playlists")
+            .and_then(|dir| self.playlists[i].save(&dir));
+        if let Err(e) = result {
+            self.message = format!("cannot save playlist: {}", e);
+        }
+    }
+
+    fn find_playlist(&self, name: &str) -> Option<usize> {
+        self.playlists.iter().position(|p| p.name == name)
+    }
+
+    fn sort_playlists(&mut self) {
+        self.playlists.sort_by_cached_key(|p| p.name.to_lowercase());
+    }
+
+    /// Creates an empty playlist, returning its index
+    fn create_playlist(&mut self, name: &str) -> Option<usize> {
+        let name = name.trim();
+        if !playlists::is_valid_name(name) {
+            self.message = format!("invalid playlist name: {}", name);
+            return None;
+        }
+        if self.find_playlist(name).is_some() {
+            self.message = format!("playlist {} already exists", name);
+            return None;
+        }
+        self.playlists.push(Playlist {
+            name: name.to_string(),
+            songs: Vec::new(),
+        });
+        self.sort_playlists();
+        let i = self.find_playlist(name)?;
+        self.save_playlist(i);
+        Some(i)
+    }
+
+    /// Renames playlist `i`, returning its new index
+    fn rename_playlist(&mut self, i: usize, name: &str) -> Option<usize> {
+        let name = name.trim();
+        if !playlists::is_valid_name(name) {
+            self.message = format!("invalid playlist name: {}", name);
+            return None;
+        }
+        let old = self.playlists[i].name.clone();
+        let result = self
+            .xdg_dirs
+            .create_data_directory("playlists")
+            .and_then(|dir| self.playlists[i].rename(&dir, name));
+        if let Err(e) = result {
+            self.message = format!("cannot rename playlist: {}", e);
+            return None;
+        }
+        for current in [&mut self.playlist, &mut self.target_playlist] {
+            if current.as_ref() == Some(&old) {
+                *current = Some(name.to_string());
+            }
+        }
+        self.sort_playlists();
+        self.find_playlist(name)
+    }
+
+    fn delete_playlist(&mut self, i: usize) {
+        let result = self
+            .xdg_dirs
+            .create_data_directory("playlists")
+            .and_then(|dir| self.playlists[i].delete(&dir));
+        if let Err(e) = result {
+            self.message = format!("cannot delete playlist: {}", e);
+            return;
+        }
+        let playlist = self.playlists.remove(i);
+        if self.target_playlist.as_ref() == Some(&playlist.name) {
+            self.target_playlist = None;
+        }
+        if self.playlist.as_ref() == Some(&playlist.name) {
+            self.playlist = None;
+            self.reorder();
+        }
+        self.message = format!("deleted playlist {}", playlist.name);
+    }
+
+    /// Updates play order after songs of playlist `i` changed, keeping the order of the rest
+    fn playlist_changed(&mut self, i: usize) {
+        if self.playlist.as_ref() != Some(&self.playlists[i].name) {
+            return;
+        }
+        let songs = self.playlist_songs(&self.playlists[i]);
+        if songs.is_empty() {
+            self.stop_playlist();
+            return;
+        }
+        let mut in_playlist = vec![false; self.json_item.len()];
+        for &idx in &songs {
+            in_playlist[idx] = true;
+        }
+        self.order.retain(|&idx| in_playlist[idx]);
+        for idx in songs {
+            if !self.order.contains(&idx) {
+                self.order.push(idx);
+            }
+        }
+        self.sync_pos();
+    }
+
+    /// Adds songs to the target playlist, or removes them if all of them are in it already
+    fn toggle_in_playlist(&mut self, songs: &[usize]) {
+        let Some(i) = self
+            .target_playlist
+            .as_ref()
+            .and_then(|name| self.find_playlist(name))
+        else {
+            self.message = "no target playlist, choose one with t in playlists (p)".to_string();
+            return;
+        };
+        let keys: Vec<String> = songs
+            .iter()
+            .map(|&idx| self.json_item[idx].audio_hash.clone())
+            .collect();
+        let playlist = &mut self.playlists[i];
+        if keys.iter().all(|k| playlist.songs.contains(k)) {
+            playlist.songs.retain(|k| !keys.contains(k));
+            self.message = format!("removed {} song(s) from {}", keys.len(), playlist.name);
+        } else {
+            let mut added = 0;
+            for key in keys {
+                if !playlist.songs.contains(&key) {
+                    playlist.songs.push(key);
+                    added += 1;
+                }
+            }
+            self.message = format!("added {} song(s) to {}", added, playlist.name);
+        }
+        self.save_playlist(i);
+        self.playlist_changed(i);
+    }
+
+    /// Switches play order to playlist `i`, starting with `start` or its first song.
+    /// Returns false if none of its songs are in the library.
+    fn start_playlist(&mut self, i: usize, start: Option<usize>) -> bool {
+        let songs = self.playlist_songs(&self.playlists[i]);
+        if songs.is_empty() {
+            return false;
+        }
+        self.playlist = Some(self.playlists[i].name.clone());
+        self.collection = None;
+        self.restart_order(start);
+        true
+    }
+
+    /// Switches play order to a collection (or the whole library) and its first song.
+    /// Returns false if the collection has no songs in the library.
+    fn start_collection(&mut self, name: Option<String>) -> bool {
+        if let Some(name) = &name
+            && !self
+                .json_item
+                .iter()
+                .any(|item| item.metadata.collections.contains(name))
+        {
+            return false;
+        }
+        self.playlist = None;
+        self.collection = name;
+        self.restart_order(None);
+        true
+    }
+
+    /// Rebuilds play order for a new pool, starting with `start` or the first song in order
+    fn restart_order(&mut self, start: Option<usize>) {
+        self.forward.clear();
+        self.previews.clear();
+        if let Some(start) = start {
+            self.idx = start;
+        }
+        self.reorder();
+        if start.is_none() {
+            self.idx = if self.shuffle == ShuffleMode::Weighted {
+                self.pick_weighted()
+            } else {
+                self.order[0]
+            };
+        }
+        self.sync_pos();
+    }
+
+    fn stop_playlist(&mut self) {
+        self.playlist = None;
+        self.reorder();
+    }
+
+    /// Writes songs to `<name>.m3u8` in the exports directory under XDG data directory
+    fn export(&mut self, name: &str, songs: &[usize]) {
+        let tracks: Vec<playlist_file::Track> = songs
+            .iter()
+            .map(|&i| self.json_item[i].track(self.is_unicode))
+            .collect();
+        let result = self
+            .xdg_dirs
+            .create_data_directory("exports")
+            .map(|dir| dir.join(format!("{}.m3u8", name)))
+            .and_then(|path| playlist_file::write(&path, &tracks).map(|_| path));
+        self.message = match result {
+            Ok(path) => format!("exported {} song(s) to {}", tracks.len(), path.display()),
+            Err(e) => format!("cannot export: {}", e),
+        };
+    }
+
+    /// Like item_to_string, with characters matching search highlighted
+    fn item_to_line(&self, i: usize) -> Line<'static> {
+        let text = self.item_to_string(i);
+        let item = &self.json_item[i];
+        let words = self.search_state.expr.highlights();
+        // title and artist are matched separately, as item_to_string shows them
+        let title = self.get_title(item);
+        let artist_offset = title.chars().count() + " - ".len();
+        let mut marked = query::highlight(&words, &title);
+        marked.extend(
+            query::highlight(&words, &self.get_artist(item))
+                .into_iter()
+                .map(|p| p + artist_offset),
+        );
+        let highlight = Style::default()
+            .fg(ratatui::style::Color::Cyan)
+            .add_modifier(Modifier::UNDERLINED);
+        let spans: Vec<Span> = text
+            .chars()
+            .enumerate()
+            .map(|(k, c)| {
+                if marked.contains(&k) {
+                    Span::styled(c.to_string(), highlight)
+                } else {
+                    Span::raw(c.to_string())
+                }
+            })
+            .collect();
+        Line::from(spans)
     }
 
     fn item_to_string(&self, i: usize) -> String {
         let item = &self.json_item[i];
-        format!("{} - {}", self.get_title(item), self.get_artist(item))
+        let marks = self.ratings.get(&item.audio_hash).marks();
+        if marks.is_empty() {
+            format!("{} - {}", self.get_title(item), self.get_artist(item))
+        } else {
+            format!(
+                "{} - {} {}",
+                self.get_title(item),
+                self.get_artist(item),
+                marks
+            )
+        }
     }
 }
 
@@ -415,8 +2135,22 @@ fn main_ui<B>(
                 let outer_block = Block::default()
                     .title(APP_DISPLAY_NAME)
                     .title(
-                        Line::from(format!("{}/{}", app.idx + 1, app.json_item.len()))
-                            .alignment(ratatui::layout::Alignment::Right),
+                        Line::from(format!(
+                            "{}{}/{}{}",
+                            app.playlist
+                                .as_ref()
+                                .or(app.collection.as_ref())
+                                .map(|name| format!("{}: ", name))
+                                .unwrap_or_default(),
+                            app.pos + 1,
+                            app.order.len(),
+                            if app.queue.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" ({} queued)", app.queue.len())
+                            }
+                        ))
+                        .alignment(ratatui::layout::Alignment::Right),
                     )
                     .borders(Borders::TOP);
                 let chunks = Layout::default()
@@ -424,27 +2158,69 @@ fn main_ui<B>(
                     .margin(1)
                     .constraints(
                         [
-                            ratatui::layout::Constraint::Percentage(10),
-                            ratatui::layout::Constraint::Percentage(90),
+                            ratatui::layout::Constraint::Length(3),
+                            ratatui::layout::Constraint::Min(0),
                         ]
                     )
                     .split(outer_block.inner(frame.area()));
                 frame.render_widget(outer_block, frame.area());
                 frame.render_widget(
                     Paragraph::new(format!(
-                        "{} - {} {:.1} / {:.1} ({}{})",
+                        "{} - {} {:.1} / {:.1} ({}{}{}{}{}{}{}, {}) {}\n{}\n{}",
                         app.title,
                         app.artist,
                         app.progress,
                         app.total,
                         if app.paused { "paused" } else { "playing" },
-                        if app.repeat { " repeat" } else { "" }
+                        if app.repeat { " repeat" } else { "" },
+                        app.ab_loop_label(),
+                        match app.speed_label() {
+                            label if label.is_empty() => label,
+                            label => format!(" {}", label),
+                        },
+                        app.shuffle.label(),
+                        if app.favourites_only {
+                            " favourites"
+                        } else {
+                            ""
+                        },
+                        if app.previewing {
+                            format!(" preview {:.0}s", app.preview_length)
+                        } else if app.start_at_preview {
+                            " from preview".to_string()
+                        } else {
+                            String::new()
+                        },
+                        app.volume_label(),
+                        app.ratings.get(&get_current_item!(app).audio_hash).marks(),
+                        app.details,
+                        app.song_stats
                     ))
                     .wrap(Wrap { trim: true }),
                     chunks[0],
                 );
                 let imgw = StatefulImage::default();
                 frame.render_stateful_widget(imgw, chunks[1], &mut app.bg_img);
+                if let Some(input) = &app.seek_prompt {
+                    // on the bottom line, left empty by the margin
+                    let area = frame.area();
+                    let line = ratatui::layout::Rect::new(
+                        area.x + 1,
+                        area.bottom().saturating_sub(1),
+                        area.width.saturating_sub(2),
+                        1,
+                    );
+                    let label = "Jump to (like 1:23, +10 or 50%): ";
+                    frame.render_widget(
+                        Paragraph::new(format!("{}{}", label, input.value()))
+                            .style(Style::default().fg(ratatui::style::Color::Yellow)),
+                        line,
+                    );
+                    frame.set_cursor_position((
+                        line.x + (label.len() + input.visual_cursor()) as u16,
+                        line.y,
+                    ));
+                }
             })
             .unwrap();
         app.ui_dirty = false;
@@ -453,6 +2229,23 @@ fn main_ui<B>(
         let tm_event = event::read().unwrap();
         if let event::Event::Key(key_event) = tm_event {
             app.ui_dirty = true;
+            if let Some(mut input) = app.seek_prompt.take() {
+                match key_event.code {
+                    event::KeyCode::Esc => {}
+                    event::KeyCode::Enter => match Seek::parse(input.value()) {
+                        Some(target) => {
+                            mpv_control_tx.send(InternalControl::Seek(target)).unwrap();
+                        }
+                        // keep the prompt open to fix the timestamp
+                        None => app.seek_prompt = Some(input),
+                    },
+                    _ => {
+                        input.handle_event(&crossterm::event::Event::Key(key_event));
+                        app.seek_prompt = Some(input);
+                    }
+                }
+                return;
+            }
             match key_event.code {
                 event::KeyCode::Char('q') => {
                     mpv_control_tx.send(InternalControl::Quit).unwrap();
@@ -461,6 +2254,7 @@ fn main_ui<B>(
                     app.set_paused(!app.paused, mpv_control_tx.clone());
                 }
                 event::KeyCode::Char('>') => {
+                    app.finish(End::Skipped);
                     app.next_idx();
                     app.open(mpv_control_tx.clone());
                     app.update_metadata(Some(picker));
@@ -474,22 +2268,163 @@ fn main_ui<B>(
                     app.toggle_unicode();
                 }
                 event::KeyCode::Char('r') => {
-                    app.repeat = !app.repeat;
+                    app.set_repeat(!app.repeat);
+                }
+                event::KeyCode::Char('z') => {
+                    app.toggle_shuffle();
+                }
+                event::KeyCode::Char('F') => {
+                    app.favourites_only = !app.favourites_only;
+                }
+                event::KeyCode::Up => {
+                    app.set_volume(app.config.volume + VOLUME_STEP, mpv_control_tx.clone());
+                }
+                event::KeyCode::Down => {
+                    app.set_volume(app.config.volume - VOLUME_STEP, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('m') => {
+                    app.toggle_mute(mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('G') => {
+                    app.add_gain(GAIN_STEP, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('g') => {
+                    app.add_gain(-GAIN_STEP, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('L') => {
+                    app.cycle_ab_loop(mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('o') => {
+                    let current = app
+                        .audio_devices
+                        .iter()
+                        .position(|d| d.name == app.audio_device);
+                    app.audio_device_state
+                        .list_state
+                        .select(current.or(Some(0)));
+                    app.ui_state = UIState::AudioDevices;
+                }
+                event::KeyCode::Char('k') => {
+                    let count = app.cues.get(&get_current_item!(app).audio_hash).len();
+                    let state = &mut app.cue_state;
+                    if state.list_state.selected().is_none() && count > 0 {
+                        state.list_state.select(Some(0));
+                    }
+                    app.ui_state = UIState::Cues;
+                }
+                event::KeyCode::Char(']') => {
+                    app.set_speed(
+                        app.speed + SPEED_STEP,
+                        app.pitch_shift,
+                        mpv_control_tx.clone(),
+                    );
+                }
+                event::KeyCode::Char('[') => {
+                    app.set_speed(
+                        app.speed - SPEED_STEP,
+                        app.pitch_shift,
+                        mpv_control_tx.clone(),
+                    );
+                }
+                event::KeyCode::Char('\\') => {
+                    app.set_speed(app.speed, !app.pitch_shift, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('D') => {
+                    app.toggle_preset(SpeedPreset::Dt, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('N') => {
+                    app.toggle_preset(SpeedPreset::Nc, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('H') => {
+                    app.toggle_preset(SpeedPreset::Ht, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char('P') => {
+                    app.start_at_preview = !app.start_at_preview;
+                }
+                event::KeyCode::Char('v') => {
+                    app.previewing = !app.previewing;
+                    if !app.previewing {
+                        app.previews.clear();
+                    } else {
+                        // preview the rest of current song from here
+                        app.preview_end = Some(
+                            app.progress
+                                .max(get_current_item!(app).metadata.preview_start())
+                                + app.preview_length,
+                        );
+                    }
+                }
+                event::KeyCode::Left | event::KeyCode::Right => {
+                    let step = app.seek_step(key_event.modifiers);
+                    let step = if key_event.code == event::KeyCode::Left {
+                        -step
+                    } else {
+                        step
+                    };
+                    mpv_control_tx
+                        .send(InternalControl::Seek(Seek::Relative(step)))
+                        .unwrap();
+                }
+                event::KeyCode::Char(c @ '1'..='9')
+                    if key_event.modifiers.contains(event::KeyModifiers::ALT) =>
+                {
+                    app.jump_to_cue(c as usize - '1' as usize, mpv_control_tx.clone());
+                }
+                event::KeyCode::Char(c @ '0'..='9') => {
+                    let percent = f64::from(c as u8 - b'0') * 10.0;
+                    mpv_control_tx
+                        .send(InternalControl::Seek(Seek::Percent(percent)))
+                        .unwrap();
                 }
-                event::KeyCode::Left => {
+                event::KeyCode::Home => {
                     mpv_control_tx
-                        .send(InternalControl::Seek(app.progress - 5.0))
+                        .send(InternalControl::Seek(Seek::Absolute(0.0)))
                         .unwrap();
                 }
-                event::KeyCode::Right => {
+                event::KeyCode::End => {
                     mpv_control_tx
-                        .send(InternalControl::Seek(app.progress + 5.0))
+                        .send(InternalControl::Seek(Seek::Absolute(-END_SECONDS)))
                         .unwrap();
                 }
+                event::KeyCode::Char('t') => {
+                    app.seek_prompt = Some(tui_input::Input::default());
+                }
                 event::KeyCode::Char('s') => {
                     app.ui_state = UIState::Search;
                 }
-                _ => {}
+                event::KeyCode::Char('l') => {
+                    app.ui_state = UIState::Queue;
+                }
+                event::KeyCode::Char('h') => {
+                    app.history_state.list_state.select(None);
+                    app.ui_state = UIState::History;
+                }
+                event::KeyCode::Char('b') => {
+                    if app.browse_state.groups.is_empty() {
+                        app.build_groups();
+                    }
+                    app.ui_state = UIState::Browse;
+                }
+                event::KeyCode::Char('p') => {
+                    let state = &mut app.playlist_state;
+                    if state.list_state.selected().is_none() && !app.playlists.is_empty() {
+                        state.list_state.select(Some(0));
+                    }
+                    app.ui_state = UIState::Playlists;
+                }
+                event::KeyCode::Char('c') => {
+                    // the first entry is the whole library
+                    let current = app
+                        .collection
+                        .as_ref()
+                        .and_then(|name| app.collections.iter().position(|(n, _)| n == name))
+                        .map_or(0, |i| i + 1);
+                    app.collection_state.list_state.select(Some(current));
+                    app.ui_state = UIState::Collections;
+                }
+                code => {
+                    app.rate(app.idx, code);
+                }
             }
         } else if let event::Event::Resize(_, _) = tm_event {
             app.ui_dirty = true;
@@ -497,6 +2432,49 @@ fn main_ui<B>(
     }
 }
 
+fn previous(current: usize, offset: usize) -> usize {
+    current.saturating_sub(offset)
+}
+
+fn next(current: usize, total: usize, offset: usize) -> usize {
+    current.saturating_add(offset).min(total - 1)
+}
+
+fn circular_previous(current: usize, total: usize, offset: usize) -> usize {
+    (current + total - (offset % total)) % total
+}
+
+fn circular_next(current: usize, total: usize, offset: usize) -> usize {
+    (current + offset) % total
+}
+
+/// Handles Up/Down/PageUp/PageDown for a list with `total` items, returning whether the key was one of them
+fn navigate_list(
+    list_state: &mut ListState,
+    total: usize,
+    page: usize,
+    code: event::KeyCode,
+) -> bool {
+    let current = list_state.selected().unwrap_or(0);
+    let i = match code {
+        event::KeyCode::Up
+        | event::KeyCode::Down
+        | event::KeyCode::PageUp
+        | event::KeyCode::PageDown
+            if total == 0 =>
+        {
+            return true;
+        }
+        event::KeyCode::Up => circular_previous(current, total, 1),
+        event::KeyCode::Down => circular_next(current, total, 1),
+        event::KeyCode::PageUp => previous(current, page),
+        event::KeyCode::PageDown => next(current, total, page),
+        _ => return false,
+    };
+    list_state.select(Some(i));
+    true
+}
+
 fn search_ui<B>(
     terminal: &mut Terminal<B>,
     app: &mut App,
@@ -528,7 +2506,13 @@ fn search_ui<B>(
                         InputMode::Editing => Style::default().fg(ratatui::style::Color::Yellow),
                     })
                     .scroll((0, scroll as u16))
-                    .block(Block::default().borders(Borders::ALL).title("Search"));
+                    .block(match &app.search_state.error {
+                        Some(error) => Block::default()
+                            .borders(Borders::ALL)
+                            .border_style(Style::default().fg(ratatui::style::Color::Red))
+                            .title(format!("Search: {}", error)),
+                        None => Block::default().borders(Borders::ALL).title("Search"),
+                    });
                 frame.render_widget(input, chunks[0]);
                 if app.search_state.input_mode == InputMode::Editing {
                     frame.set_cursor_position((
@@ -542,13 +2526,16 @@ fn search_ui<B>(
                     .search_state
                     .results
                     .iter()
-                    .map(|&i| ListItem::new(app.item_to_string(i)))
+                    .map(|&i| ListItem::new(app.item_to_line(i)))
                     .collect();
-                let items_title = if let Some(idx) = app.search_state.list_state.selected() {
+                let mut items_title = if let Some(idx) = app.search_state.list_state.selected() {
                     format!("Results ({}/{})", idx + 1, items.len())
                 } else {
                     "Results".to_string()
                 };
+                if !app.message.is_empty() {
+                    items_title = format!("{} - {}", items_title, app.message);
+                }
                 let items = List::new(items)
                     .block(
                         Block::default()
@@ -563,7 +2550,6 @@ fn search_ui<B>(
                     )
                     .highlight_style(
                         Style::default()
-                            .add_modifier(Modifier::BOLD)
                             .add_modifier(Modifier::BOLD)
                             .fg(ratatui::style::Color::Yellow),
                     )
@@ -580,55 +2566,20 @@ fn search_ui<B>(
         let tm_event = event::read().unwrap();
         if let event::Event::Key(key_event) = tm_event {
             app.ui_dirty = true;
-
-            fn previous(current: usize, offset: usize) -> usize {
-                current.saturating_sub(offset)
-            }
-
-            fn next(current: usize, total: usize, offset: usize) -> usize {
-                current.saturating_add(offset).min(total - 1)
-            }
-
-            fn circular_previous(current: usize, total: usize, offset: usize) -> usize {
-                (current + total - (offset % total)) % total
-            }
-
-            fn circular_next(current: usize, total: usize, offset: usize) -> usize {
-                (current + offset) % total
-            }
+            app.message.clear();
 
             match app.search_state.input_mode {
                 InputMode::Normal => match key_event.code {
-                    event::KeyCode::Up => {
-                        let i = circular_previous(
-                            app.search_state.list_state.selected().unwrap_or(0),
-                            app.search_state.results.len(),
-                            1,
-                        );
-                        app.search_state.list_state.select(Some(i));
-                    }
-                    event::KeyCode::Down => {
-                        let i = circular_next(
-                            app.search_state.list_state.selected().unwrap_or(0),
-                            app.search_state.results.len(),
-                            1,
-                        );
-                        app.search_state.list_state.select(Some(i));
-                    }
-                    event::KeyCode::PageUp => {
-                        let i = previous(
-                            app.search_state.list_state.selected().unwrap_or(0),
-                            app.search_state.list_height.into(),
-                        );
-                        app.search_state.list_state.select(Some(i));
-                    }
-                    event::KeyCode::PageDown => {
-                        let i = next(
-                            app.search_state.list_state.selected().unwrap_or(0),
+                    event::KeyCode::Up
+                    | event::KeyCode::Down
+                    | event::KeyCode::PageUp
+                    | event::KeyCode::PageDown => {
+                        navigate_list(
+                            &mut app.search_state.list_state,
                             app.search_state.results.len(),
                             app.search_state.list_height.into(),
+                            key_event.code,
                         );
-                        app.search_state.list_state.select(Some(i));
                     }
                     event::KeyCode::Char('u') => {
                         app.toggle_unicode();
@@ -638,32 +2589,69 @@ fn search_ui<B>(
                     }
                     event::KeyCode::Enter => {
                         if let Some(i) = app.search_state.list_state.selected() {
-                            app.idx = app.search_state.results[i];
-                            app.open(mpv_control_tx.clone());
-                            app.update_metadata(Some(picker));
-                            app.set_paused(false, mpv_control_tx.clone());
+                            app.play_idx(
+                                app.search_state.results[i],
+                                mpv_control_tx.clone(),
+                                picker,
+                            );
+                            app.ui_state = UIState::Main;
+                        }
+                    }
+                    event::KeyCode::Char('n') => {
+                        if let Some(i) = app.search_state.list_state.selected() {
+                            app.queue.push_front(app.search_state.results[i]);
+                        }
+                    }
+                    event::KeyCode::Char('a') => {
+                        if let Some(i) = app.search_state.list_state.selected() {
+                            app.queue.push_back(app.search_state.results[i]);
+                        }
+                    }
+                    event::KeyCode::Char('p') => {
+                        if let Some(i) = app.search_state.list_state.selected() {
+                            app.toggle_in_playlist(&[app.search_state.results[i]]);
+                        }
+                    }
+                    event::KeyCode::Char('e') => {
+                        let results = app.search_state.results.clone();
+                        app.export("search", &results);
+                    }
+                    event::KeyCode::Char('v') => {
+                        // audition results from the selected one, leaving the queue as it is
+                        if let Some(i) = app.search_state.list_state.selected() {
+                            let results = &app.search_state.results[i..];
+                            let (first, rest) = (results[0], results[1..].to_vec());
+                            app.previewing = true;
+                            app.play_idx(first, mpv_control_tx.clone(), picker);
+                            app.previews = rest.into();
                             app.ui_state = UIState::Main;
                         }
                     }
-                    _ => {}
+                    code => {
+                        if let Some(i) = app.search_state.list_state.selected() {
+                            app.rate(app.search_state.results[i], code);
+                        }
+                    }
                 },
                 InputMode::Editing => match key_event.code {
                     event::KeyCode::Esc => {
                         app.ui_state = UIState::Main;
                     }
                     event::KeyCode::Enter | event::KeyCode::Tab => {
-                        app.search_state.results = app.search(app.search_state.input.value());
-                        if !app.search_state.results.is_empty() {
-                            app.search_state.list_state.select(Some(0));
-                        } else {
-                            app.search_state.list_state.select(None);
+                        // keep editing to fix the query
+                        if app.search_state.error.is_none() {
+                            app.search_state.input_mode = InputMode::Normal;
                         }
-                        app.search_state.input_mode = InputMode::Normal;
                     }
                     _ => {
-                        app.search_state
+                        let changed = app
+                            .search_state
                             .input
-                            .handle_event(&crossterm::event::Event::Key(key_event));
+                            .handle_event(&crossterm::event::Event::Key(key_event))
+                            .is_some_and(|c| c.value);
+                        if changed {
+                            app.update_search();
+                        }
                     }
                 },
             }
@@ -676,21 +2664,149 @@ fn search_ui<B>(
     }
 }
 
+fn song_list<'a>(items: Vec<ListItem<'a>>, title: String) -> List<'a> {
+    List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(ratatui::style::Color::Yellow),
+        )
+        .highlight_symbol(">")
+        .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print most played songs, artists and sources
+    Stats {
+        /// Number of entries in each list
+        #[clap(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Measure loudness of songs not analyzed yet (needs ffmpeg), so that loudness
+    /// normalization can use a static gain instead of the live loudnorm filter
+    Analyze {
+        #[command(flatten)]
+        library: LibraryArgs,
+
+        /// Analyze all songs again
+        #[clap(long, default_value_t = false)]
+        force: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct LibraryArgs {
+    /// Path to osu!lazer data directory (or its client.realm), osu!stable install directory
+    /// (or its osu!.db), RealmHashExtractor's generated JSON file, an .osz file or a music folder
+    #[clap(required = true)]
+    library: Option<PathBuf>,
+
+    /// Path to osu! files directory (defaults to "files" next to client.realm),
+    /// or osu!stable's Songs directory (defaults to "Songs" next to osu!.db)
+    osu_path: Option<PathBuf>,
+
+    /// Collection name, can be given multiple times.
+    /// If not provided, all collections will be used for osu!lazer (songs not in any collection will be ignored),
+    /// and all songs will be used for osu!stable.
+    #[clap(short, long = "collection")]
+    collections: Vec<String>,
+
+    /// Additional .osz file, or folder of .osz and music files (mp3, ogg, ...), can be given multiple times
+    #[clap(long = "add")]
+    extra_sources: Vec<PathBuf>,
+}
+
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Path to RealmHashExtractor's generated JSON file
-    json_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Path to osu! files directory
-    osu_path: PathBuf,
+    #[command(flatten)]
+    library: LibraryArgs,
 
-    /// Controls loudness normalization
+    /// Controls loudness normalization. Songs measured by `play analyze` get a static gain,
+    /// others the live loudnorm filter.
     #[clap(long = "loudnorm", default_value_t = true, action = clap::ArgAction::SetTrue)]
     #[clap(long = "no-loudnorm", action = clap::ArgAction::SetFalse)]
     loudnorm: bool,
 
+    /// Gain of analyzed songs for loudness normalization
+    #[clap(long, value_enum, default_value_t)]
+    replaygain: ReplayGainMode,
+
     #[clap(long, default_value_t = false)]
     force_pixelart: bool,
+
+    /// Play order [default: once]
+    #[clap(long, value_enum)]
+    shuffle: Option<ShuffleMode>,
+
+    /// Only play favourite songs (except for queued ones)
+    #[clap(long, default_value_t = false)]
+    favourites: bool,
+
+    /// Continue from where the last run stopped
+    #[clap(long, default_value_t = false)]
+    resume: bool,
+
+    /// Play a playlist instead of the whole library
+    #[clap(long)]
+    playlist: Option<String>,
+
+    /// Write the library (or songs of --playlist, or results of --query) to an M3U/M3U8
+    /// or XSPF (by extension) file, then exit
+    #[clap(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// Search query for --export, like in search mode
+    #[clap(long, requires = "export")]
+    query: Option<String>,
+
+    /// Import an M3U/M3U8 or XSPF file as a playlist named after the file, then exit
+    #[clap(long, value_name = "FILE", conflicts_with = "export")]
+    import: Option<PathBuf>,
+
+    /// Start each song at its preview point (toggle with P)
+    #[clap(long, default_value_t = false)]
+    start_at_preview: bool,
+
+    /// Preview mode: play SECONDS (default 15) from the preview point of each song,
+    /// then go to the next one (toggle with v)
+    #[clap(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "15")]
+    preview: Option<f64>,
+
+    /// Preload the next song, so that mpv plays it right after the current one without a gap
+    #[clap(long, default_value_t = false)]
+    gapless: bool,
+
+    /// Crossfade over SECONDS at transitions: the end of a song fades out while the next one
+    /// fades in over it (implies --gapless)
+    #[clap(long, value_name = "SECONDS", default_value_t = 0.0)]
+    crossfade: f64,
+
+    /// Playback speed, from 0.5 to 2
+    #[clap(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Let pitch change with speed, instead of correcting it
+    #[clap(long, default_value_t = false)]
+    pitch_shift: bool,
+
+    /// Speed preset like osu! mods, instead of --speed and --pitch-shift
+    #[clap(long = "mod", value_name = "MOD", value_enum, conflicts_with_all = ["speed", "pitch_shift"])]
+    speed_preset: Option<SpeedPreset>,
+
+    /// mpv audio device to play on, like "pulse/alsa_output.usb-..." or "auto" (see mpv
+    /// --audio-device=help). Defaults to the one last picked in the player.
+    #[clap(long, value_name = "NAME")]
+    audio_device: Option<String>,
+
+    /// Random seed, to get the same play order each time
+    #[clap(long)]
+    seed: Option<u64>,
 }
 
 pub fn init_tui() -> io::Result<Terminal<impl ratatui::backend::Backend>> {
@@ -714,14 +2830,296 @@ pub fn init_panic_hook() {
     }));
 }
 
+/// Loads the library and extra sources, with errors naming the file that could not be read
+fn load_library(args: &LibraryArgs, xdg_dirs: &xdg::BaseDirectories) -> io::Result<Vec<JsonItem>> {
+    let context = |path: &Path| {
+        let path = path.display().to_string();
+        move |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path, e))
+    };
+    let osz_cache = xdg_dirs.create_cache_directory("osz").unwrap();
+    let mut beatmap_cache = beatmap::Cache::load(&xdg_dirs.get_cache_home().join("beatmaps.json"));
+    // required by clap unless running a subcommand
+    let library = args.library.as_ref().unwrap();
+    let db_path = if library.join("osu!.db").exists() {
+        library.join("osu!.db")
+    } else if library.join("client.realm").exists() {
+        library.join("client.realm")
+    } else {
+        library.clone()
+    };
+    let mut json_item = if db_path.extension().is_some_and(|ext| ext == "realm") {
+        let osu_path = args
+            .osu_path
+            .clone()
+            .unwrap_or_else(|| db_path.with_file_name("files"));
+        realm::load_songs(&db_path, &osu_path, &args.collections, &mut beatmap_cache)
+            .map_err(context(&db_path))?
+    } else if db_path.file_name().is_some_and(|name| name == "osu!.db") {
+        let songs_path = args
+            .osu_path
+            .clone()
+            .unwrap_or_else(|| db_path.with_file_name("Songs"));
+        stable::load_songs(&db_path, &songs_path, &args.collections, &mut beatmap_cache)
+            .map_err(context(&db_path))?
+    } else if db_path.is_dir() || local::is_osz(&db_path) {
+        local::load_songs(&db_path, &osz_cache, &mut beatmap_cache).map_err(context(&db_path))?
+    } else {
+        let json_file = std::fs::read_to_string(library).map_err(context(library))?;
+        let osu_path = args.osu_path.clone().ok_or_else(|| {
+            io::Error::other("osu! files directory is required when using JSON file")
+        })?;
+        let mut json_item: Vec<JsonItem> = serde_json::from_str(&json_file)
+            .map_err(|e| context(library)(io::Error::other(e)))?;
+        for item in json_item.iter_mut() {
+            item.layout = FileLayout::Hashed(osu_path.clone());
+        }
+        json_item
+    };
+    for source in &args.extra_sources {
+        json_item.extend(
+            local::load_songs(source, &osz_cache, &mut beatmap_cache).map_err(context(source))?,
+        );
+    }
+    store::save_placed(
+        "beatmap cache",
+        xdg_dirs.place_cache_file("beatmaps.json"),
+        |path| beatmap_cache.save(path),
+    );
+    Ok(json_item)
+}
+
+/// Handles --import, saving the songs found as a playlist
+fn import_playlist(
+    json_item: &[JsonItem],
+    file: &Path,
+    name: Option<&str>,
+    xdg_dirs: &xdg::BaseDirectories,
+) {
+    let paths = playlist_file::read(file).unwrap_or_else(|e| {
+        eprintln!("cannot read {}: {}", file.display(), e);
+        std::process::exit(1);
+    });
+    let name = name
+        .map(str::to_string)
+        .or_else(|| Some(file.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    if !playlists::is_valid_name(&name) {
+        eprintln!("invalid playlist name: {}", name);
+        std::process::exit(1);
+    }
+    let (songs, unknown) = match_files(json_item, &paths);
+    let mut keys: Vec<String> = Vec::with_capacity(songs.len());
+    for i in songs {
+        if !keys.contains(&json_item[i].audio_hash) {
+            keys.push(json_item[i].audio_hash.clone());
+        }
+    }
+    let playlist = Playlist {
+        name: name.clone(),
+        songs: keys,
+    };
+    let result = xdg_dirs
+        .create_data_directory("playlists")
+        .and_then(|dir| playlist.save(&dir));
+    if let Err(e) = result {
+        eprintln!("cannot save playlist: {}", e);
+        std::process::exit(1);
+    }
+    println!(
+        "imported {} song(s) into playlist {}, {} not found in the library",
+        playlist.songs.len(),
+        name,
+        unknown
+    );
+}
+
+fn save_loudness(loudness: &Loudness, xdg_dirs: &xdg::BaseDirectories) {
+    store::save_placed(
+        "loudness",
+        xdg_dirs.place_cache_file("loudness.json"),
+        |path| store::save_json(path, loudness),
+    );
+}
+
+/// Handles the analyze subcommand, measuring songs with one ffmpeg per CPU
+fn analyze_songs(json_item: &[JsonItem], force: bool, xdg_dirs: &xdg::BaseDirectories) {
+    let mut loudness: Loudness = store::load_json(&xdg_dirs.get_cache_home().join("loudness.json"));
+    let mut seen = HashSet::new();
+    let todo: Vec<&JsonItem> = json_item
+        .iter()
+        .filter(|item| seen.insert(&item.audio_hash))
+        .filter(|item| force || loudness.get(&item.audio_hash).is_none())
+        .collect();
+    if todo.is_empty() {
+        println!("all {} song(s) are analyzed", seen.len());
+        return;
+    }
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    let mut failed = 0;
+    std::thread::scope(|scope| {
+        for _ in 0..workers.min(todo.len()) {
+            let tx = tx.clone();
+            let (next, todo) = (&next, &todo);
+            scope.spawn(move || {
+                while let Some(item) = todo.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = loudness::analyze(&item.get_file_path(&item.audio_hash));
+                    if tx.send((*item, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (done, (item, result)) in rx.iter().enumerate() {
+            let name = format!("{} - {}", item.metadata.artist, item.metadata.title);
+            match result {
+                Ok(analysis) => {
+                    println!(
+                        "[{}/{}] {}: {:.1} LUFS, peak {:.1} dBTP",
+                        done + 1,
+                        todo.len(),
+                        name,
+                        analysis.integrated,
+                        analysis.true_peak
+                    );
+                    loudness.songs.insert(item.audio_hash.clone(), analysis);
+                }
+                Err(e) => {
+                    eprintln!("[{}/{}] {}: {}", done + 1, todo.len(), name, e);
+                    failed += 1;
+                }
+            }
+            // keep progress if interrupted
+            if (done + 1) % 100 == 0 {
+                save_loudness(&loudness, xdg_dirs);
+            }
+        }
+    });
+    save_loudness(&loudness, xdg_dirs);
+    println!(
+        "analyzed {} song(s), {} failed",
+        todo.len() - failed,
+        failed
+    );
+}
+
+/// Handles --export, writing the library, a playlist or search results
+fn export_songs(
+    json_item: &[JsonItem],
+    file: &Path,
+    playlist: Option<&Playlist>,
+    query: Option<&str>,
+    xdg_dirs: &xdg::BaseDirectories,
+) {
+    let mut songs: Vec<usize> = match playlist {
+        Some(playlist) => {
+            let by_hash: HashMap<&str, usize> = json_item
+                .iter()
+                .enumerate()
+                .map(|(i, item)| (item.audio_hash.as_str(), i))
+                .collect();
+            playlist
+                .songs
+                .iter()
+                .filter_map(|k| by_hash.get(k.as_str()).copied())
+                .collect()
+        }
+        None => (0..json_item.len()).collect(),
+    };
+    if let Some(query) = query {
+        let expr = query::parse(query).unwrap_or_else(|e| {
+            eprintln!("invalid query: {}", e);
+            std::process::exit(1);
+        });
+        let ratings: Ratings = store::load_json(&xdg_dirs.get_data_home().join("ratings.json"));
+        let stats: Stats = store::load_json(&xdg_dirs.get_data_home().join("stats.json"));
+        let results = search_songs(json_item, &ratings, &stats, &expr);
+        songs = results.into_iter().filter(|i| songs.contains(i)).collect();
+    }
+    let tracks: Vec<playlist_file::Track> =
+        songs.iter().map(|&i| json_item[i].track(false)).collect();
+    if let Err(e) = playlist_file::write(file, &tracks) {
+        eprintln!("cannot write {}: {}", file.display(), e);
+        std::process::exit(1);
+    }
+    println!("exported {} song(s) to {}", tracks.len(), file.display());
+}
+
 fn main() {
     let args = Cli::parse();
-    let json_file = std::fs::read_to_string(&args.json_file).unwrap();
-    let mut json_item: Vec<JsonItem> = serde_json::from_str(&json_file).unwrap();
-    json_item.shuffle(&mut rand::thread_rng());
-
     let xdg_dirs = xdg::BaseDirectories::with_prefix(APP_NAME).unwrap();
 
+    match &args.command {
+        Some(Command::Stats { limit }) => {
+            let stats: Stats = store::load_json(&xdg_dirs.get_data_home().join("stats.json"));
+            stats::print(&stats, *limit);
+            return;
+        }
+        Some(Command::Analyze { library, force }) => {
+            let json_item = load_library(library, &xdg_dirs).unwrap_or_else(|e| {
+                eprintln!("cannot read library: {}", e);
+                std::process::exit(1);
+            });
+            analyze_songs(&json_item, *force, &xdg_dirs);
+            return;
+        }
+        None => (),
+    }
+
+    let json_item = load_library(&args.library, &xdg_dirs).unwrap_or_else(|e| {
+        eprintln!("cannot read library: {}", e);
+        std::process::exit(1);
+    });
+    // there would be nothing to play
+    if json_item.is_empty() {
+        if args.library.collections.is_empty() {
+            eprintln!("no songs found in the library");
+        } else {
+            eprintln!(
+                "no songs match collection {}",
+                args.library.collections.join(", ")
+            );
+        }
+        std::process::exit(1);
+    }
+    if let Some(file) = &args.import {
+        import_playlist(&json_item, file, args.playlist.as_deref(), &xdg_dirs);
+        return;
+    }
+    let mut playlist = None;
+    if let Some(name) = &args.playlist {
+        let playlists = playlists::load_all(&xdg_dirs.get_data_home().join("playlists"));
+        let Some(found) = playlists.into_iter().find(|p| &p.name == name) else {
+            eprintln!("playlist {} not found", name);
+            std::process::exit(1);
+        };
+        if !found
+            .songs
+            .iter()
+            .any(|k| json_item.iter().any(|item| &item.audio_hash == k))
+        {
+            eprintln!("playlist {} has no songs in the library", name);
+            std::process::exit(1);
+        }
+        playlist = Some(found);
+    }
+    if let Some(file) = &args.export {
+        export_songs(
+            &json_item,
+            file,
+            playlist.as_ref(),
+            args.query.as_deref(),
+            &xdg_dirs,
+        );
+        return;
+    }
+    let rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
     init_panic_hook();
     let mut terminal = init_tui().unwrap();
     terminal.clear().unwrap();
@@ -734,11 +3132,27 @@ fn main() {
 
     let mpv = Mpv::with_initializer(|c| c.set_property("load-scripts", "no")).unwrap();
     mpv.set_property("vo", "null").unwrap();
-    mpv.set_property("volume", 100).unwrap();
-    if args.loudnorm {
-        mpv.set_property("af", "lavfi=[loudnorm=I=-14:TP=-2:LRA=11]")
-            .unwrap();
+    let mut config: Config = store::load_json(&xdg_dirs.get_config_home().join("config.json"));
+    config.volume = config.volume.clamp(0.0, MAX_VOLUME);
+    let initial_volume = config.volume;
+    mpv.set_property("volume", initial_volume).unwrap();
+    mpv.set_property("mute", config.muted).unwrap();
+    let audio_device = args
+        .audio_device
+        .clone()
+        .or_else(|| config.audio_device.clone())
+        .unwrap_or_else(|| "auto".to_string());
+    mpv.set_property("audio-device", audio_device.as_str())
+        .unwrap();
+    let crossfade = args.crossfade.max(0.0);
+    let gapless = args.gapless || crossfade > 0.0;
+    if gapless {
+        mpv.set_property("gapless-audio", "yes").unwrap();
     }
+    let mut tail = (crossfade > 0.0).then(|| {
+        CrossfadeTail::new(crossfade, &audio_device, config.muted)
+            .expect("cannot create mpv instance for crossfade")
+    });
 
     let souvlaki_config = PlatformConfig {
         dbus_name: APP_ID,
@@ -769,12 +3183,41 @@ fn main() {
             ev_ctx
                 .observe_property("duration", libmpv::Format::Double, 1)
                 .unwrap();
+            ev_ctx
+                .observe_property("playlist-pos", libmpv::Format::Int64, 2)
+                .unwrap();
+            // as a JSON string, to not deal with mpv nodes
+            ev_ctx
+                .observe_property("audio-device-list", libmpv::Format::String, 3)
+                .unwrap();
+            // seeking is not possible until the opened file is loaded
+            let mut loading = false;
+            let mut pending_seek = None;
+            let mut current: Option<PathBuf> = None;
+            // song after the current one in mpv's playlist
+            let mut preloaded: Option<PathBuf> = None;
+            let mut duration = 0.0;
+            let mut volume = initial_volume;
+            // of current song, in dB
+            let mut gain = 0.0;
+            let mut live_loudnorm = false;
+            // volume factor of crossfade, to set volume only when it changes
+            let mut fade = 1.0;
+            // current song was gone on to by a crossfade, and is still fading in
+            let mut fading_in = false;
             loop {
                 let event = ev_ctx.wait_event(0.16).unwrap_or(Err(libmpv::Error::Null));
                 match event {
                     Ok(Event::StartFile) => {}
+                    Ok(Event::FileLoaded) => {
+                        loading = false;
+                        if let Some(target) = pending_seek.take() {
+                            seek(&mpv, target);
+                        }
+                    }
                     Ok(Event::EndFile(r)) => {
-                        if r == mpv_end_file_reason::Eof {
+                        // with a preloaded song, mpv goes on to it, which shows as playlist-pos
+                        if r == mpv_end_file_reason::Eof && preloaded.is_none() {
                             mpv_event_tx.send(InternalEvent::Eof).unwrap();
                         }
                     }
@@ -785,15 +3228,88 @@ fn main() {
                     }) => match name {
                         "time-pos" => {
                             if let PropertyData::Double(time) = change {
+                                if let Some(tail) = tail.as_mut()
+                                    && let Some(path) = &current
+                                    && preloaded.is_some()
+                                    && duration > 2.0 * crossfade
+                                {
+                                    let fade_start = duration - crossfade;
+                                    match tail.state {
+                                        TailState::Idle
+                                            if time >= fade_start - CROSSFADE_LOAD_AHEAD =>
+                                        {
+                                            tail.load(
+                                                path,
+                                                fade_start.max(time),
+                                                duration,
+                                                gain,
+                                                &mpv,
+                                            );
+                                        }
+                                        TailState::Loaded { start, .. } if time >= fade_start => {
+                                            // seeked past where it was loaded at
+                                            if time - start > 0.5 {
+                                                tail.load(path, time, duration, gain, &mpv);
+                                            }
+                                            tail.start(volume);
+                                            // fade out is up to the tail now
+                                            let _ = mpv.command("playlist-next", &[]);
+                                            fading_in = true;
+                                        }
+                                        TailState::Loaded { start, .. }
+                                            if time < start - 2.0 * CROSSFADE_LOAD_AHEAD =>
+                                        {
+                                            // seeked back
+                                            tail.unload();
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                if crossfade > 0.0 && duration > 0.0 {
+                                    if time >= crossfade {
+                                        fading_in = false;
+                                    }
+                                    let fade_in = if fading_in { time / crossfade } else { 1.0 };
+                                    let factor =
+                                        fade_in.min((duration - time) / crossfade).clamp(0.0, 1.0);
+                                    if (factor - fade).abs() >= 0.01 {
+                                        fade = factor;
+                                        mpv.set_property("volume", mpv_volume(volume, gain, fade))
+                                            .unwrap();
+                                    }
+                                }
                                 mpv_event_tx
                                     .send(InternalEvent::Pos(time.max(0.0)))
                                     .unwrap();
                             }
                         }
                         "duration" => {
-                            if let PropertyData::Double(duration) = change {
+                            if let PropertyData::Double(d) = change {
+                                duration = d.max(0.0);
+                                mpv_event_tx
+                                    .send(InternalEvent::Duration(duration))
+                                    .unwrap();
+                            }
+                        }
+                        "playlist-pos" => {
+                            // the preloaded song is always the second entry
+                            if let PropertyData::Int64(1) = change
+                                && preloaded.is_some()
+                            {
+                                current = preloaded.take();
+                                loading = true;
+                                pending_seek = None;
+                                duration = 0.0;
+                                // keep the playlist as just the current song
+                                mpv.command("playlist-remove", &["0"]).unwrap();
+                                mpv_event_tx.send(InternalEvent::Advanced).unwrap();
+                            }
+                        }
+                        "audio-device-list" => {
+                            if let PropertyData::Str(list) = change {
+                                let devices = serde_json::from_str(list).unwrap_or_default();
                                 mpv_event_tx
-                                    .send(InternalEvent::Duration(duration.max(0.0)))
+                                    .send(InternalEvent::AudioDevices(devices))
                                     .unwrap();
                             }
                         }
@@ -811,17 +3327,105 @@ fn main() {
                     match control {
                         InternalControl::Play => {
                             mpv.set_property("pause", false).unwrap();
+                            if let Some(tail) = &tail
+                                && let TailState::Playing { .. } = tail.state
+                            {
+                                let _ = tail.mpv.set_property("pause", false);
+                            }
                         }
                         InternalControl::Pause => {
                             mpv.set_property("pause", true).unwrap();
+                            if let Some(tail) = &tail {
+                                let _ = tail.mpv.set_property("pause", true);
+                            }
                         }
-                        InternalControl::Seek(time) => {
-                            mpv.set_property("time-pos", time).unwrap();
+                        InternalControl::Seek(target) => {
+                            fading_in = false;
+                            if loading {
+                                pending_seek = Some(target);
+                            } else {
+                                seek(&mpv, target);
+                            }
                         }
                         InternalControl::Open(path) => {
-                            mpv.command("loadfile", &[path.to_str().unwrap(), "replace"])
+                            // a file mpv cannot open ends right away, without an Eof
+                            let _ = mpv.command("loadfile", &[&path.to_string_lossy(), "replace"]);
+                            loading = true;
+                            pending_seek = None;
+                            current = Some(path);
+                            preloaded = None;
+                            duration = 0.0;
+                            fading_in = false;
+                            if let Some(tail) = tail.as_mut() {
+                                tail.unload();
+                            }
+                        }
+                        InternalControl::Volume(v) => {
+                            volume = v;
+                            mpv.set_property("volume", mpv_volume(volume, gain, fade))
+                                .unwrap();
+                        }
+                        InternalControl::Mute(muted) => {
+                            mpv.set_property("mute", muted).unwrap();
+                            if let Some(tail) = &tail {
+                                let _ = tail.mpv.set_property("mute", muted);
+                            }
+                        }
+                        InternalControl::Gain(g) => {
+                            gain = g;
+                            mpv.set_property("volume", mpv_volume(volume, gain, fade))
                                 .unwrap();
                         }
+                        InternalControl::Loudnorm(on) => {
+                            if on != live_loudnorm {
+                                live_loudnorm = on;
+                                mpv.set_property("af", if on { loudness::LIVE_FILTER } else { "" })
+                                    .unwrap();
+                            }
+                        }
+                        InternalControl::Speed(speed, pitch_shift) => {
+                            mpv.set_property("speed", speed).unwrap();
+                            mpv.set_property("audio-pitch-correction", !pitch_shift)
+                                .unwrap();
+                        }
+                        InternalControl::AbLoop(points) => match points {
+                            Some((a, b)) => {
+                                mpv.set_property("ab-loop-a", a).unwrap();
+                                mpv.set_property("ab-loop-b", b).unwrap();
+                            }
+                            None => {
+                                mpv.set_property("ab-loop-a", "no").unwrap();
+                                mpv.set_property("ab-loop-b", "no").unwrap();
+                            }
+                        },
+                        InternalControl::AudioDevice(name) => {
+                            // mpv reopens audio output on the new device, keeping playback going
+                            if let Err(e) = mpv.set_property("audio-device", name.as_str()) {
+                                let current = mpv
+                                    .get_property::<String>("audio-device")
+                                    .unwrap_or_else(|_| "auto".to_string());
+                                mpv_event_tx
+                                    .send(InternalEvent::AudioDeviceFailed(
+                                        current,
+                                        format!("cannot play on {}: {:?}", name, e),
+                                    ))
+                                    .unwrap();
+                            } else if let Some(tail) = &tail {
+                                let _ = tail.mpv.set_property("audio-device", name);
+                            }
+                        }
+                        InternalControl::Preload(path) => {
+                            // removes everything but the current song
+                            let _ = mpv.command("playlist-clear", &[]);
+                            // without it in the playlist, the current song ends with an Eof as usual
+                            preloaded = path.filter(|path| {
+                                mpv.command("loadfile", &[&path.to_string_lossy(), "append"])
+                                    .is_ok()
+                            });
+                            if let Some(tail) = tail.as_mut() {
+                                tail.unload();
+                            }
+                        }
                         InternalControl::Quit => {
                             mpv.command("quit", &[]).unwrap();
                             mpv_event_tx.send(InternalEvent::Quit).unwrap();
@@ -829,14 +3433,74 @@ fn main() {
                         }
                     }
                 }
+
+                if let Some(tail) = tail.as_mut() {
+                    tail.update(volume);
+                }
             }
         })
         .unwrap();
 
-    let mut app = App::new(&picker, controls, &args.osu_path, json_item, xdg_dirs);
+    let mut app = App::new(
+        &picker,
+        controls,
+        json_item,
+        xdg_dirs,
+        args.shuffle.unwrap_or_default(),
+        rng,
+    );
+    app.gapless = gapless;
+    app.loudnorm = args.loudnorm;
+    app.replaygain = args.replaygain;
+    let _ = app.controls.set_volume(config.volume / 100.0);
+    app.config = config;
+    app.audio_device = audio_device;
+    match args.speed_preset {
+        Some(preset) => app.set_speed(preset.speed(), preset.pitch_shift(), mpv_control_tx.clone()),
+        None => app.set_speed(args.speed, args.pitch_shift, mpv_control_tx.clone()),
+    }
+    app.favourites_only = args.favourites;
+    app.start_at_preview = args.start_at_preview;
+    app.previewing = args.preview.is_some();
+    if let Some(length) = args.preview {
+        app.preview_length = length.max(1.0);
+    }
+    let session = args
+        .resume
+        .then(|| app.xdg_dirs.find_state_file("session.json"))
+        .flatten()
+        .and_then(|path| Session::load(&path));
+    let mut position = 0.0;
+    if let Some(session) = session {
+        position = app.restore(session);
+        // explicitly given shuffle mode wins over the saved one
+        if let Some(shuffle) = args.shuffle
+            && shuffle != app.shuffle
+        {
+            app.set_shuffle(shuffle);
+        }
+        app.favourites_only |= args.favourites;
+        app.start_at_preview |= args.start_at_preview;
+        app.previewing |= args.preview.is_some();
+    }
+    if let Some(i) = args
+        .playlist
+        .as_ref()
+        .and_then(|name| app.find_playlist(name))
+    {
+        app.start_playlist(i, None);
+        position = 0.0;
+    }
 
+    app.sync_order_controls();
     app.open(mpv_control_tx.clone());
     app.update_metadata(Some(&picker));
+    if position > 0.0 {
+        mpv_control_tx
+            .send(InternalControl::Seek(Seek::Absolute(position)))
+            .unwrap();
+    }
+    let mut last_saved = Instant::now();
 
     loop {
         if let Ok(msg) = mpv_event_rx.try_recv() {
@@ -844,26 +3508,84 @@ fn main() {
             match msg {
                 InternalEvent::Pos(time) => {
                     app.update_progress(time);
+                    if app.preview_over() {
+                        app.finish(End::Switched);
+                        if !app.repeat {
+                            app.next_idx();
+                        }
+                        app.open(mpv_control_tx.clone());
+                        app.update_metadata(Some(&picker));
+                    }
                 }
                 InternalEvent::Eof => {
+                    app.finish(End::Completed);
                     if !app.repeat {
                         app.next_idx();
                     }
                     app.open(mpv_control_tx.clone());
                     app.update_metadata(Some(&picker));
                 }
+                InternalEvent::Advanced => {
+                    app.finish(End::Completed);
+                    let preloaded = app.preloaded.take();
+                    if !app.repeat {
+                        app.next_idx();
+                    }
+                    if preloaded == Some(app.idx) {
+                        app.started(mpv_control_tx.clone());
+                    } else {
+                        // the next song changed just as the preloaded one started
+                        app.open(mpv_control_tx.clone());
+                    }
+                    app.update_metadata(Some(&picker));
+                }
                 InternalEvent::Duration(duration) => {
                     app.update_duration(duration);
                 }
+                InternalEvent::AudioDevices(devices) => {
+                    app.audio_devices = devices;
+                }
+                InternalEvent::AudioDeviceFailed(current, error) => {
+                    app.audio_device_failed(&current, error);
+                }
                 InternalEvent::Quit => {
+                    app.finish(End::Switched);
+                    app.save_stats();
+                    app.save_session();
                     break;
                 }
             }
         }
-        if app.ui_state == UIState::Main {
-            main_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker);
-        } else {
-            search_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker);
+        match app.ui_state {
+            UIState::Main => main_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker),
+            UIState::Search => search_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker),
+            UIState::Queue => {
+                screens::queue_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker)
+            }
+            UIState::History => {
+                screens::history_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker)
+            }
+            UIState::Browse => {
+                screens::browse_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker)
+            }
+            UIState::Playlists => {
+                screens::playlists_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker)
+            }
+            UIState::Collections => {
+                screens::collections_ui(&mut terminal, &mut app, mpv_control_tx.clone(), &picker)
+            }
+            UIState::Cues => screens::cues_ui(&mut terminal, &mut app, mpv_control_tx.clone()),
+            UIState::AudioDevices => {
+                screens::audio_devices_ui(&mut terminal, &mut app, mpv_control_tx.clone())
+            }
+        }
+
+        app.sync_preload(mpv_control_tx.clone());
+
+        if last_saved.elapsed() >= SESSION_SAVE_INTERVAL {
+            app.save_stats();
+            app.save_session();
+            last_saved = Instant::now();
         }
 
         for event in souvlaki_rx.try_iter() {
@@ -879,6 +3601,7 @@ fn main() {
                     app.set_paused(true, mpv_control_tx.clone());
                 }
                 MediaControlEvent::Next => {
+                    app.finish(End::Skipped);
                     app.next_idx();
                     app.open(mpv_control_tx.clone());
                     app.update_metadata(Some(&picker));
@@ -888,6 +3611,21 @@ fn main() {
                     app.open(mpv_control_tx.clone());
                     app.update_metadata(Some(&picker));
                 }
+                MediaControlEvent::SetVolume(volume) => {
+                    app.set_volume(volume * 100.0, mpv_control_tx.clone());
+                }
+                // keeps the shuffle mode when it is already on
+                MediaControlEvent::SetShuffle(on) if on != (app.shuffle != ShuffleMode::Off) => {
+                    app.set_shuffle(if on {
+                        ShuffleMode::Once
+                    } else {
+                        ShuffleMode::Off
+                    });
+                }
+                MediaControlEvent::SetLoopStatus(status) => {
+                    // there is no stopping at the end, so None is the same as Playlist
+                    app.set_repeat(status == LoopStatus::Track);
+                }
                 _ => (),
             }
         }
@@ -895,3 +3633,64 @@ fn main() {
 
     restore_tui().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_favours_liked_and_rarely_played_songs() {
+        let unrated = Rating::default();
+        let favourite = Rating {
+            favourite: true,
+            stars: 0,
+        };
+        assert!(weight(0, None, unrated, false) > weight(4, None, unrated, false));
+        assert!(weight(0, None, unrated, false) > weight(0, Some(0), unrated, false));
+        assert!(weight(0, Some(0), unrated, false) < weight(0, Some(100), unrated, false));
+        assert!(weight(0, None, favourite, false) > weight(0, None, unrated, false));
+    }
+
+    #[test]
+    fn favourites_only_weighs_each_song_by_its_own_rating() {
+        let favourite = Rating {
+            favourite: true,
+            stars: 3,
+        };
+        let rated = Rating {
+            favourite: false,
+            stars: 5,
+        };
+        assert!(weight(0, None, favourite, true) > 0.0);
+        assert_eq!(weight(0, None, rated, true), 0.0);
+        assert!(weight(0, None, rated, false) > 0.0);
+    }
+
+    fn item(audio_hash: &str, layout: FileLayout) -> JsonItem {
+        JsonItem {
+            audio_hash: audio_hash.to_string(),
+            bg_hashes: Vec::new(),
+            metadata: Metadata::default(),
+            layout,
+        }
+    }
+
+    #[test]
+    fn match_files_by_path_or_file_name() {
+        let json_item = [
+            item("abcdef", FileLayout::Hashed(PathBuf::from("/osu/files"))),
+            item(
+                "Set/audio.mp3",
+                FileLayout::Relative(PathBuf::from("/osu/Songs")),
+            ),
+        ];
+        let paths = [
+            PathBuf::from("/osu/Songs/Set/audio.mp3"),
+            PathBuf::from("/osu/files/a/ab/abcdef"),
+            PathBuf::from("/elsewhere/abcdef"),
+            PathBuf::from("/elsewhere/audio.mp3"),
+            PathBuf::from("/elsewhere/other.mp3"),
+        ];
+        assert_eq!(match_files(&json_item, &paths), (vec![1, 0, 0, 1], 1));
+    }
+}
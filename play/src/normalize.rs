@@ -0,0 +1,248 @@
+// Text normalization for search: lowercase, full-width and half-width forms
+// to their usual forms, katakana to hiragana, accented Latin letters to plain
+// ones, and a romaji reading of kana so that "kakeru" finds "カケル" (kanji
+// have no reading here). Every normalized char keeps the index of the
+// original char it comes from, so that matches could be highlighted in the
+// original text.
+
+/// Accented lowercase letters and their plain forms
+const DIACRITICS: &[(&str, char)] = &[
+    ("àáâãäåāăą", 'a'),
+    ("çćĉċč", 'c'),
+    ("ďđ", 'd'),
+    ("èéêëēĕėęě", 'e'),
+    ("ĝğġģ", 'g'),
+    ("ĥħ", 'h'),
+    ("ìíîïĩīĭįı", 'i'),
+    ("ĵ", 'j'),
+    ("ķ", 'k'),
+    ("ĺļľŀł", 'l'),
+    ("ñńņň", 'n'),
+    ("òóôõöøōŏő", 'o'),
+    ("ŕŗř", 'r'),
+    ("śŝşšș", 's'),
+    ("ţťŧț", 't'),
+    ("ùúûüũūŭůűų", 'u'),
+    ("ŵ", 'w'),
+    ("ýÿŷ", 'y'),
+    ("źżž", 'z'),
+];
+
+/// Half-width katakana from U+FF66, as full-width hiragana
+const HALF_WIDTH_KANA: &str = "をぁぃぅぇぉゃゅょっーあいうえおかきくけこさしすせそたちつてとなにぬねのはひふへほまみむめもやゆよらりるれろわん";
+
+/// Kana taking dakuten (voiced, next code point) and handakuten (semi-voiced, the one after)
+const VOICEABLE: &str = "かきくけこさしすせそたちつてとはひふへほ";
+const SEMI_VOICEABLE: &str = "はひふへほ";
+
+#[rustfmt::skip]
+const ROMAJI: &[(char, &str)] = &[
+    ('あ', "a"), ('い', "i"), ('う', "u"), ('え', "e"), ('お', "o"),
+    ('か', "ka"), ('き', "ki"), ('く', "ku"), ('け', "ke"), ('こ', "ko"),
+    ('さ', "sa"), ('し', "shi"), ('す', "su"), ('せ', "se"), ('そ', "so"),
+    ('た', "ta"), ('ち', "chi"), ('つ', "tsu"), ('て', "te"), ('と', "to"),
+    ('な', "na"), ('に', "ni"), ('ぬ', "nu"), ('ね', "ne"), ('の', "no"),
+    ('は', "ha"), ('ひ', "hi"), ('ふ', "fu"), ('へ', "he"), ('ほ', "ho"),
+    ('ま', "ma"), ('み', "mi"), ('む', "mu"), ('め', "me"), ('も', "mo"),
+    ('や', "ya"), ('ゆ', "yu"), ('よ', "yo"),
+    ('ら', "ra"), ('り', "ri"), ('る', "ru"), ('れ', "re"), ('ろ', "ro"),
+    ('わ', "wa"), ('ゐ', "i"), ('ゑ', "e"), ('を', "wo"), ('ん', "n"),
+    ('が', "ga"), ('ぎ', "gi"), ('ぐ', "gu"), ('げ', "ge"), ('ご', "go"),
+    ('ざ', "za"), ('じ', "ji"), ('ず', "zu"), ('ぜ', "ze"), ('ぞ', "zo"),
+    ('だ', "da"), ('ぢ', "ji"), ('づ', "zu"), ('で', "de"), ('ど', "do"),
+    ('ば', "ba"), ('び', "bi"), ('ぶ', "bu"), ('べ', "be"), ('ぼ', "bo"),
+    ('ぱ', "pa"), ('ぴ', "pi"), ('ぷ', "pu"), ('ぺ', "pe"), ('ぽ', "po"),
+    ('ゔ', "vu"),
+    // small kana, after other kana like "ふぁ" (fua), which fuzzy matching still finds as "fa"
+    ('ぁ', "a"), ('ぃ', "i"), ('ぅ', "u"), ('ぇ', "e"), ('ぉ', "o"),
+    ('ゃ', "ya"), ('ゅ', "yu"), ('ょ', "yo"), ('ゎ', "wa"),
+];
+
+fn fold(c: char) -> char {
+    let c = c.to_lowercase().next().unwrap_or(c);
+    match c as u32 {
+        // full-width ASCII
+        0xFF01..=0xFF5E => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        0x3000 => ' ',
+        // katakana to hiragana
+        0x30A1..=0x30F6 => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        0xFF66..=0xFF9D => HALF_WIDTH_KANA
+            .chars()
+            .nth((c as u32 - 0xFF66) as usize)
+            .unwrap_or(c),
+        _ => DIACRITICS
+            .iter()
+            .find(|(accented, _)| accented.contains(c))
+            .map_or(c, |&(_, plain)| plain),
+    }
+}
+
+/// Applies a (half-width or combining) dakuten or handakuten to kana
+fn voice(c: char, mark: char) -> Option<char> {
+    match mark {
+        '\u{3099}' | '\u{309B}' | '\u{FF9E}' if c == 'う' => Some('ゔ'),
+        '\u{3099}' | '\u{309B}' | '\u{FF9E}' if VOICEABLE.contains(c) => {
+            char::from_u32(c as u32 + 1)
+        }
+        '\u{309A}' | '\u{309C}' | '\u{FF9F}' if SEMI_VOICEABLE.contains(c) => {
+            char::from_u32(c as u32 + 2)
+        }
+        _ => None,
+    }
+}
+
+/// Normalized chars of `text`, with indexes of the original chars
+pub fn normalize(text: &str) -> Vec<(char, usize)> {
+    let mut result: Vec<(char, usize)> = Vec::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c as u32 {
+            // combining diacritical marks
+            0x0300..=0x036F => continue,
+            // sound marks, combining, spacing or half-width
+            0x3099..=0x309C | 0xFF9E | 0xFF9F => {
+                if let Some(last) = result.last_mut()
+                    && let Some(voiced) = voice(last.0, c)
+                {
+                    last.0 = voiced;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push((fold(c), i));
+    }
+    result
+}
+
+fn romaji(c: char) -> Option<&'static str> {
+    ROMAJI.iter().find(|&&(k, _)| k == c).map(|&(_, r)| r)
+}
+
+/// Romaji reading of normalized text, or None if it has no kana
+pub fn romanize(chars: &[(char, usize)]) -> Option<Vec<(char, usize)>> {
+    if !chars.iter().any(|&(c, _)| romaji(c).is_some() || c == 'っ') {
+        return None;
+    }
+    let mut result: Vec<(char, usize)> = Vec::with_capacity(chars.len() * 2);
+    // small tsu doubles the next consonant
+    let mut double: Option<usize> = None;
+    let mut k = 0;
+    while k < chars.len() {
+        let (c, origin) = chars[k];
+        k += 1;
+        if c == 'っ' {
+            double = Some(origin);
+            continue;
+        }
+        if c == 'ー' {
+            // long vowel mark repeats the previous vowel
+            if let Some(&(last, _)) = result.last()
+                && "aiueo".contains(last)
+            {
+                result.push((last, origin));
+            }
+            continue;
+        }
+        let Some(reading) = romaji(c) else {
+            result.push((c, origin));
+            double = None;
+            continue;
+        };
+        if let Some(tsu) = double.take()
+            && let Some(first) = reading.chars().next().filter(|c| !"aiueon".contains(*c))
+        {
+            result.push((first, tsu));
+        }
+        // "きゃ" is "kya", "しゃ" is "sha"
+        if let Some(&(small, small_origin)) = chars.get(k)
+            && let Some(yoon) = ['ゃ', 'ゅ', 'ょ'].iter().position(|&y| y == small)
+            && reading.len() > 1
+            && reading.ends_with('i')
+        {
+            let stem = &reading[..reading.len() - 1];
+            result.extend(stem.chars().map(|c| (c, origin)));
+            if !(stem.ends_with("sh") || stem.ends_with("ch") || stem.ends_with('j')) {
+                result.push(('y', small_origin));
+            }
+            result.push((['a', 'u', 'o'][yoon], small_origin));
+            k += 1;
+            continue;
+        }
+        result.extend(reading.chars().map(|c| (c, origin)));
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(text: &str) -> String {
+        normalize(text).into_iter().map(|(c, _)| c).collect()
+    }
+
+    fn romaji(text: &str) -> Option<String> {
+        romanize(&normalize(text)).map(|chars| chars.into_iter().map(|(c, _)| c).collect())
+    }
+
+    #[test]
+    fn folds_chars() {
+        for (text, expected) in [
+            ("YOASOBI", "yoasobi"),
+            ("ＡＢＣ！１２", "abc!12"),
+            ("a\u{3000}b", "a b"),
+            ("Café Ñandú", "cafe nandu"),
+            ("ŁÓDŹ", "lodz"),
+            ("カタカナ", "かたかな"),
+            ("ｶﾀｶﾅ", "かたかな"),
+            ("夜に駆ける", "夜に駆ける"),
+        ] {
+            assert_eq!(normalized(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn joins_marks() {
+        // combining accents are dropped, sound marks voice the kana before them
+        assert_eq!(normalize("e\u{301}a"), [('e', 0), ('a', 2)]);
+        assert_eq!(normalize("か\u{3099}"), [('が', 0)]);
+        assert_eq!(normalize("ハ\u{309A}"), [('ぱ', 0)]);
+        assert_eq!(normalize("ｶﾞｷﾞ"), [('が', 0), ('ぎ', 2)]);
+        assert_eq!(normalize("ｳﾞ"), [('ゔ', 0)]);
+        assert_eq!(normalize("ﾊﾟ"), [('ぱ', 0)]);
+        // a mark after a kana it cannot voice is kept
+        assert_eq!(normalized("あ\u{309B}"), "あ\u{309B}");
+    }
+
+    #[test]
+    fn romanizes_kana() {
+        for (text, expected) in [
+            ("かける", "kakeru"),
+            ("しんじつ", "shinjitsu"),
+            ("さっぽろ", "sapporo"),
+            ("ちょっと", "chotto"),
+            ("きゃ", "kya"),
+            ("しゃしん", "shashin"),
+            ("じゃ", "ja"),
+            ("ラーメン", "raamen"),
+            ("ヴ", "vu"),
+            ("ふぁ", "fua"),
+            ("夜にカケル", "夜nikakeru"),
+            ("a ソング", "a songu"),
+        ] {
+            assert_eq!(romaji(text).as_deref(), Some(expected), "{}", text);
+        }
+        assert_eq!(romaji("abc 夜"), None);
+    }
+
+    #[test]
+    fn romaji_keeps_origins() {
+        let origins: Vec<usize> = romanize(&normalize("きょっと"))
+            .unwrap()
+            .into_iter()
+            .map(|(_, i)| i)
+            .collect();
+        // k y o from きょ, the doubled t from っ
+        assert_eq!(origins, [0, 1, 1, 2, 3, 3]);
+    }
+}
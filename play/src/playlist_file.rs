@@ -0,0 +1,133 @@
+// M3U/M3U8 and XSPF playlist files, for sharing playlists with mpv, other
+// players and phones. Exported entries point at the actual audio files, and
+// imported entries are paths (or file URLs) to be mapped back to songs.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use url::Url;
+
+pub struct Track {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    /// In seconds
+    pub length: Option<f64>,
+    pub cover: Option<PathBuf>,
+}
+
+fn is_xspf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xspf"))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn file_url(path: &Path) -> Option<String> {
+    Url::from_file_path(path).ok().map(|url| url.to_string())
+}
+
+fn to_m3u(tracks: &[Track]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for track in tracks {
+        // -1 is unknown length
+        let length = track.length.map_or(-1, |secs| secs.round() as i64);
+        // commas are fine after the first one, but line breaks would end the entry
+        let name = format!("{} - {}", track.artist, track.title).replace(['\r', '\n'], " ");
+        let _ = writeln!(out, "#EXTINF:{},{}", length, name);
+        if let Some(cover) = &track.cover {
+            let _ = writeln!(out, "#EXTIMG:{}", cover.display());
+        }
+        let _ = writeln!(out, "{}", track.path.display());
+    }
+    out
+}
+
+fn to_xspf(tracks: &[Track]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for track in tracks {
+        let Some(location) = file_url(&track.path) else {
+            continue;
+        };
+        out.push_str("    <track>\n");
+        let _ = writeln!(out, "      <location>{}</location>", escape_xml(&location));
+        let _ = writeln!(out, "      <title>{}</title>", escape_xml(&track.title));
+        let _ = writeln!(
+            out,
+            "      <creator>{}</creator>",
+            escape_xml(&track.artist)
+        );
+        if let Some(secs) = track.length {
+            let _ = writeln!(out, "      <duration>{}</duration>", (secs * 1000.0) as u64);
+        }
+        if let Some(cover) = track.cover.as_deref().and_then(file_url) {
+            let _ = writeln!(out, "      <image>{}</image>", escape_xml(&cover));
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+/// Writes tracks as XSPF for `.xspf` files, and as (UTF-8) M3U otherwise
+pub fn write(path: &Path, tracks: &[Track]) -> io::Result<()> {
+    let content = if is_xspf(path) {
+        to_xspf(tracks)
+    } else {
+        to_m3u(tracks)
+    };
+    fs::write(path, content)
+}
+
+/// Resolves an entry, which may be a file URL or a path relative to the playlist file
+fn resolve(entry: &str, base: &Path) -> PathBuf {
+    if let Ok(url) = Url::parse(entry)
+        && url.scheme() == "file"
+        && let Ok(path) = url.to_file_path()
+    {
+        return path;
+    }
+    base.join(entry)
+}
+
+/// Reads entries of an M3U/M3U8 or XSPF file, in order
+pub fn read(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let content = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let entries: Vec<String> = if is_xspf(path) {
+        content
+            .split("<location>")
+            .skip(1)
+            .filter_map(|rest| rest.split_once("</location>"))
+            .map(|(location, _)| unescape_xml(location.trim()))
+            .collect()
+    } else {
+        content
+            .lines()
+            .map(|line| line.trim_start_matches('\u{FEFF}').trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    };
+    Ok(entries.iter().map(|entry| resolve(entry, base)).collect())
+}
@@ -0,0 +1,77 @@
+// User playlists, one JSON file per playlist under `playlists` in the XDG data
+// directory. Songs are stored by audio file key, and songs missing from the
+// current library are kept, so that playlists survive library changes.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::store;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Playlist {
+    /// From file name
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub songs: Vec<String>,
+}
+
+/// Names become file names, so path separators and leading dots are not allowed
+pub fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && !name.contains(['/', '\\']) && !name.starts_with('.')
+}
+
+fn path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+/// Loads all playlists, sorted by name
+pub fn load_all(dir: &Path) -> Vec<Playlist> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut playlists: Vec<Playlist> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let mut playlist: Playlist =
+                serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+            playlist.name = path.file_stem()?.to_string_lossy().into_owned();
+            Some(playlist)
+        })
+        .collect();
+    playlists.sort_by_cached_key(|p| p.name.to_lowercase());
+    playlists
+}
+
+impl Playlist {
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        store::save_json_pretty(&path(dir, &self.name), self)
+    }
+
+    pub fn delete(&self, dir: &Path) -> io::Result<()> {
+        fs::remove_file(path(dir, &self.name))
+    }
+
+    pub fn rename(&mut self, dir: &Path, name: &str) -> io::Result<()> {
+        if name == self.name {
+            return Ok(());
+        }
+        let to = path(dir, name);
+        if to.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("playlist {} already exists", name),
+            ));
+        }
+        fs::rename(path(dir, &self.name), to)?;
+        self.name = name.to_string();
+        Ok(())
+    }
+}
@@ -0,0 +1,579 @@
+// Search query language, like `artist:camellia source:"touhou" -tag:remix len:<180 rating:>=4`.
+//
+// Terms are ANDed, `OR` (or `|`) has lower precedence, `-` negates a term or
+// a parenthesized group. A term is a word or "quoted phrase", optionally
+// prefixed by `field:`. Numeric fields take an optional comparison
+// (`<`, `<=`, `>`, `>=`, `=`). Words with an unknown field (like "Re:Zero")
+// are searched as plain text. Text is matched fuzzily, and the score is used
+// to rank results.
+
+use std::fmt;
+
+use crate::{Metadata, fuzzy::fuzzy_match, ratings::Rating, stats::SongStats};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextField {
+    /// Title, artist, source and tags
+    Any,
+    Title,
+    Artist,
+    Source,
+    Tag,
+    Creator,
+    Difficulty,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberField {
+    /// In seconds
+    Length,
+    Bpm,
+    Rating,
+    Plays,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    /// Matches everything
+    All,
+    Text(TextField, String),
+    Number(NumberField, Cmp, f64),
+    Favourite,
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    /// Char offset in the query
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.position + 1)
+    }
+}
+
+fn error<T>(message: impl Into<String>, position: usize) -> Result<T, ParseError> {
+    Err(ParseError {
+        message: message.into(),
+        position,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Or,
+    Not,
+    /// Optional field, value
+    Term(Option<String>, String),
+}
+
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn is_special(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '|'
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let position = i;
+        let simple = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            '|' => Some(TokenKind::Or),
+            // a lone "-" is searched as is
+            '-' if chars.get(i + 1).is_some_and(|&n| !n.is_whitespace()) => Some(TokenKind::Not),
+            _ => None,
+        };
+        if let Some(kind) = simple {
+            tokens.push(Token { kind, position });
+            i += 1;
+            continue;
+        }
+
+        let mut field = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        while i < chars.len() && !is_special(chars[i]) {
+            match chars[i] {
+                '"' => {
+                    let start = i;
+                    i += 1;
+                    while i < chars.len() && chars[i] != '"' {
+                        value.push(chars[i]);
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return error("unterminated quote", start);
+                    }
+                    quoted = true;
+                }
+                ':' if field.is_none() && !quoted && !value.is_empty() => {
+                    field = Some(std::mem::take(&mut value));
+                }
+                c => value.push(c),
+            }
+            i += 1;
+        }
+        let kind = if !quoted && field.is_none() && value == "OR" {
+            TokenKind::Or
+        } else {
+            TokenKind::Term(field, value)
+        };
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+/// Parses "<=4", ">180", "3:00" (as seconds), ...
+fn parse_number(value: &str, position: usize) -> Result<(Cmp, f64), ParseError> {
+    let (cmp, rest) = if let Some(rest) = value.strip_prefix("<=") {
+        (Cmp::Le, rest)
+    } else if let Some(rest) = value.strip_prefix(">=") {
+        (Cmp::Ge, rest)
+    } else if let Some(rest) = value.strip_prefix('<') {
+        (Cmp::Lt, rest)
+    } else if let Some(rest) = value.strip_prefix('>') {
+        (Cmp::Gt, rest)
+    } else if let Some(rest) = value.strip_prefix('=') {
+        (Cmp::Eq, rest)
+    } else {
+        (Cmp::Eq, value)
+    };
+    let number = match rest.split_once(':') {
+        Some((min, sec)) => min
+            .parse::<f64>()
+            .ok()
+            .zip(sec.parse::<f64>().ok())
+            .map(|(min, sec)| min * 60.0 + sec),
+        None => rest.parse().ok(),
+    };
+    match number {
+        Some(number) => Ok((cmp, number)),
+        None => error(format!("expected a number, got \"{}\"", value), position),
+    }
+}
+
+fn make_term(field: Option<String>, value: String, position: usize) -> Result<Expr, ParseError> {
+    let Some(field) = field else {
+        return Ok(Expr::Text(TextField::Any, value));
+    };
+    let text = |f| Ok(Expr::Text(f, value.clone()));
+    let number = |f| parse_number(&value, position).map(|(cmp, n)| Expr::Number(f, cmp, n));
+    match field.to_lowercase().as_str() {
+        "title" => text(TextField::Title),
+        "artist" => text(TextField::Artist),
+        "source" => text(TextField::Source),
+        "tag" | "tags" => text(TextField::Tag),
+        "creator" | "mapper" => text(TextField::Creator),
+        "diff" | "difficulty" => text(TextField::Difficulty),
+        "len" | "length" => number(NumberField::Length),
+        "bpm" => number(NumberField::Bpm),
+        "rating" | "stars" => number(NumberField::Rating),
+        "plays" => number(NumberField::Plays),
+        "is" => match value.to_lowercase().as_str() {
+            "fav" | "favourite" | "favorite" => Ok(Expr::Favourite),
+            _ => error(format!("unknown \"is:{}\"", value), position),
+        },
+        // not a field, like "Re:Zero"
+        _ => Ok(Expr::Text(TextField::Any, format!("{}:{}", field, value))),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Char length of the query, for errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |t| t.position)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut alternatives = vec![self.parse_and()?];
+        while self.peek() == Some(&TokenKind::Or) {
+            self.pos += 1;
+            alternatives.push(self.parse_and()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Expr::Or(alternatives)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = Vec::new();
+        while let Some(kind) = self.peek() {
+            if matches!(kind, TokenKind::Or | TokenKind::RParen) {
+                break;
+            }
+            terms.push(self.parse_unary()?);
+        }
+        match terms.len() {
+            0 => error("expected a search term", self.position()),
+            1 => Ok(terms.pop().unwrap()),
+            _ => Ok(Expr::And(terms)),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let position = self.position();
+        let Some(kind) = self.peek().cloned() else {
+            return error("expected a search term", position);
+        };
+        self.pos += 1;
+        match kind {
+            TokenKind::Not => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            TokenKind::LParen => {
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&TokenKind::RParen) {
+                    return error("missing \")\"", self.position());
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            TokenKind::Term(field, value) => make_term(field, value, position),
+            TokenKind::RParen | TokenKind::Or => error("expected a search term", position),
+        }
+    }
+}
+
+pub fn parse(query: &str) -> Result<Expr, ParseError> {
+    let tokens = tokenize(query)?;
+    if tokens.is_empty() {
+        return Ok(Expr::All);
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: query.chars().count(),
+    };
+    let expr = parser.parse_or()?;
+    if parser.pos < parser.tokens.len() {
+        // only a stray ")" stops parse_or early
+        return error("unmatched \")\"", parser.position());
+    }
+    Ok(expr)
+}
+
+/// What a query is matched against
+pub struct Song<'a> {
+    pub metadata: &'a Metadata,
+    pub rating: Rating,
+    pub stats: Option<&'a SongStats>,
+}
+
+impl Song<'_> {
+    /// Texts of a field, with weights of their scores
+    fn texts(&self, field: TextField) -> Vec<(&str, i64)> {
+        let m = self.metadata;
+        match field {
+            TextField::Any => vec![
+                (&m.title, 3),
+                (&m.artist, 3),
+                (&m.title_unicode, 3),
+                (&m.artist_unicode, 3),
+                (&m.source, 2),
+                (&m.tags, 1),
+            ],
+            TextField::Title => vec![(&m.title, 1), (&m.title_unicode, 1)],
+            TextField::Artist => vec![(&m.artist, 1), (&m.artist_unicode, 1)],
+            TextField::Source => vec![(&m.source, 1)],
+            TextField::Tag => vec![(&m.tags, 1)],
+            TextField::Creator => vec![(&m.creator, 1)],
+            TextField::Difficulty => m
+                .difficulties
+                .iter()
+                .map(|d| (d.name.as_str(), 1))
+                .collect(),
+        }
+    }
+
+    /// (min, max) of a numeric field
+    fn range(&self, field: NumberField) -> Option<(f64, f64)> {
+        let single = |v: f64| Some((v, v));
+        match field {
+            NumberField::Length => self
+                .metadata
+                .length
+                .map(|l| l as f64 / 1000.0)
+                .or_else(|| self.stats.and_then(|s| s.length))
+                .and_then(single),
+            NumberField::Bpm => self.metadata.bpm,
+            NumberField::Rating => single(self.rating.stars as f64),
+            NumberField::Plays => single(self.stats.map_or(0, |s| s.plays) as f64),
+        }
+    }
+}
+
+impl Expr {
+    /// Returns the score of a matching song, higher is better
+    pub fn score(&self, song: &Song) -> Option<i64> {
+        match self {
+            Expr::All => Some(0),
+            Expr::Text(field, value) => song
+                .texts(*field)
+                .into_iter()
+                .filter_map(|(text, weight)| fuzzy_match(value, text).map(|(s, _)| s * weight))
+                .max(),
+            Expr::Number(field, cmp, value) => {
+                let (min, max) = song.range(*field)?;
+                // a range (like BPM of a set) matches if any value in it does
+                let matched = match cmp {
+                    Cmp::Eq => min - 0.5 < *value && *value < max + 0.5,
+                    Cmp::Lt => min < *value,
+                    Cmp::Le => min <= *value,
+                    Cmp::Gt => max > *value,
+                    Cmp::Ge => max >= *value,
+                };
+                matched.then_some(0)
+            }
+            Expr::Favourite => song.rating.favourite.then_some(0),
+            Expr::Not(expr) => match expr.score(song) {
+                Some(_) => None,
+                None => Some(0),
+            },
+            Expr::And(exprs) => exprs.iter().map(|e| e.score(song)).sum(),
+            Expr::Or(exprs) => exprs.iter().filter_map(|e| e.score(song)).max(),
+        }
+    }
+
+    /// Words to highlight in titles and artists, from terms not negated
+    pub fn highlights(&self) -> Vec<&str> {
+        match self {
+            Expr::Text(TextField::Any | TextField::Title | TextField::Artist, value) => {
+                vec![value]
+            }
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                exprs.iter().flat_map(|e| e.highlights()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Char indices in `text` to highlight for `words`
+pub fn highlight(words: &[&str], text: &str) -> Vec<usize> {
+    let mut positions: Vec<usize> = words
+        .iter()
+        .filter_map(|word| fuzzy_match(word, text))
+        .flat_map(|(_, positions)| positions)
+        .collect();
+    positions.sort_unstable();
+    positions.dedup();
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compact form of an expression, like "(Any:a | -Tag:b)"
+    fn show(expr: &Expr) -> String {
+        let join = |exprs: &[Expr], sep| {
+            let parts: Vec<_> = exprs.iter().map(show).collect();
+            format!("({})", parts.join(sep))
+        };
+        match expr {
+            Expr::All => "*".to_string(),
+            Expr::Text(field, value) => format!("{:?}:{}", field, value),
+            Expr::Number(field, cmp, value) => {
+                let cmp = match cmp {
+                    Cmp::Eq => "=",
+                    Cmp::Lt => "<",
+                    Cmp::Le => "<=",
+                    Cmp::Gt => ">",
+                    Cmp::Ge => ">=",
+                };
+                format!("{:?}{}{}", field, cmp, value)
+            }
+            Expr::Favourite => "fav".to_string(),
+            Expr::Not(expr) => format!("-{}", show(expr)),
+            Expr::And(exprs) => join(exprs, " "),
+            Expr::Or(exprs) => join(exprs, " | "),
+        }
+    }
+
+    #[test]
+    fn parses() {
+        for (query, expected) in [
+            ("", "*"),
+            ("  ", "*"),
+            ("a b", "(Any:a Any:b)"),
+            // OR binds looser than the implicit AND
+            ("a b OR c", "((Any:a Any:b) | Any:c)"),
+            ("a | b c", "(Any:a | (Any:b Any:c))"),
+            ("(a | b) c", "((Any:a | Any:b) Any:c)"),
+            ("-(a | b) c", "(-(Any:a | Any:b) Any:c)"),
+            ("-tag:remix", "-Tag:remix"),
+            ("--a", "--Any:a"),
+            // a lone "-" is text, and so are a quoted or lowercase "or"
+            ("a - b", "(Any:a Any:- Any:b)"),
+            ("a \"OR\" or b", "(Any:a Any:OR Any:or Any:b)"),
+            // unknown fields are text
+            ("Re:Zero", "Any:Re:Zero"),
+            ("a:b:c", "Any:a:b:c"),
+            ("artist:\"camellia feat\"", "Artist:camellia feat"),
+            (
+                "TITLE:x mapper:y diff:z tags:w",
+                "(Title:x Creator:y Difficulty:z Tag:w)",
+            ),
+            ("len:<3:00", "Length<180"),
+            ("length:>=1:30.5", "Length>=90.5"),
+            ("bpm:>180", "Bpm>180"),
+            ("rating:4", "Rating=4"),
+            ("stars:<=2 plays:=0", "(Rating<=2 Plays=0)"),
+            ("is:fav", "fav"),
+            ("is:Favorite", "fav"),
+        ] {
+            assert_eq!(show(&parse(query).unwrap()), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        for (query, position) in [
+            ("\"abc", 0),
+            ("a \"b", 2),
+            ("(a", 2),
+            ("a)", 1),
+            ("a |", 3),
+            ("| a", 0),
+            ("()", 1),
+            ("a -(", 4),
+            ("len:abc", 0),
+            ("a len:3:xx", 2),
+            ("is:new", 0),
+        ] {
+            let error = match parse(query) {
+                Ok(expr) => panic!("{} parsed as {}", query, show(&expr)),
+                Err(error) => error,
+            };
+            assert_eq!(error.position, position, "{}: {}", query, error);
+        }
+    }
+
+    fn metadata() -> Metadata {
+        Metadata {
+            title: "Yoru ni Kakeru".to_string(),
+            title_unicode: "夜に駆ける".to_string(),
+            artist: "YOASOBI".to_string(),
+            source: "Monogatari".to_string(),
+            tags: "pop jpop".to_string(),
+            length: Some(261000),
+            bpm: Some((130.0, 130.0)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scores() {
+        let metadata = metadata();
+        let stats = SongStats {
+            plays: 3,
+            ..Default::default()
+        };
+        let song = Song {
+            metadata: &metadata,
+            rating: Rating {
+                favourite: true,
+                stars: 4,
+            },
+            stats: Some(&stats),
+        };
+        let score = |query: &str| parse(query).unwrap().score(&song);
+        for (query, matches) in [
+            ("", true),
+            ("yoasobi", true),
+            ("zzz", false),
+            ("-yoasobi", false),
+            ("yoasobi zzz", false),
+            ("yoasobi OR zzz", true),
+            ("-(zzz | pop)", false),
+            ("-(zzz | rock)", true),
+            ("title:yoasobi", false),
+            ("artist:yoasobi", true),
+            ("source:monogatari", true),
+            ("tag:jpop", true),
+            ("len:>4:00", true),
+            ("len:<4:00", false),
+            ("len:261", true),
+            ("bpm:130", true),
+            ("bpm:131", false),
+            ("rating:>=4 is:fav", true),
+            ("rating:5", false),
+            ("plays:>3", false),
+            ("plays:3", true),
+        ] {
+            assert_eq!(score(query).is_some(), matches, "{}", query);
+        }
+        // AND sums scores and OR takes the best
+        let (yoru, yoasobi) = (score("yoru").unwrap(), score("yoasobi").unwrap());
+        assert_eq!(score("yoru yoasobi"), Some(yoru + yoasobi));
+        assert_eq!(score("yoru OR yoasobi"), Some(yoru.max(yoasobi)));
+        // title and artist weigh more than tags
+        assert!(score("pop").unwrap() < score("kakeru").unwrap());
+    }
+
+    #[test]
+    fn length_falls_back_to_stats() {
+        let metadata = Metadata::default();
+        let stats = SongStats {
+            length: Some(100.0),
+            ..Default::default()
+        };
+        let song = |stats| Song {
+            metadata: &metadata,
+            rating: Rating::default(),
+            stats,
+        };
+        let query = parse("len:<2:00").unwrap();
+        assert!(query.score(&song(Some(&stats))).is_some());
+        // unknown length matches no length query
+        assert!(query.score(&song(None)).is_none());
+    }
+
+    #[test]
+    fn highlights_terms_not_negated() {
+        let query = parse("yoru -kakeru (artist:yoa | tag:pop) source:x").unwrap();
+        assert_eq!(query.highlights(), ["yoru", "yoa"]);
+        assert_eq!(
+            highlight(&query.highlights(), "Yoru ni Kakeru"),
+            [0, 1, 2, 3]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Favourites and star ratings, kept in the XDG data directory and keyed by
+// audio file key, so that they survive regenerating the library.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+pub const MAX_STARS: u8 = 5;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Rating {
+    pub favourite: bool,
+    /// 0 (not rated) to MAX_STARS
+    pub stars: u8,
+}
+
+impl Rating {
+    /// Marks shown next to song names
+    pub fn marks(&self) -> String {
+        let mut marks = String::new();
+        if self.favourite {
+            marks.push('♥');
+        }
+        if self.stars > 0 {
+            if !marks.is_empty() {
+                marks.push(' ');
+            }
+            marks.extend((0..self.stars).map(|_| '★'));
+        }
+        marks
+    }
+
+    /// Stars as 0 to 1, like MPRIS xesam:userRating. None when not rated.
+    pub fn normalized(&self) -> Option<f64> {
+        (self.stars > 0).then(|| self.stars as f64 / MAX_STARS as f64)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Ratings {
+    pub songs: HashMap<String, Rating>,
+}
+
+impl Ratings {
+    pub fn get(&self, key: &str) -> Rating {
+        self.songs.get(key).copied().unwrap_or_default()
+    }
+
+    fn update(&mut self, key: &str, f: impl FnOnce(&mut Rating)) {
+        let rating = self.songs.entry(key.to_string()).or_default();
+        f(rating);
+        if !rating.favourite && rating.stars == 0 {
+            self.songs.remove(key);
+        }
+    }
+
+    pub fn toggle_favourite(&mut self, key: &str) {
+        self.update(key, |r| r.favourite = !r.favourite);
+    }
+
+    /// Changes rating by `delta` stars, within 0 to MAX_STARS
+    pub fn add_stars(&mut self, key: &str, delta: i8) {
+        self.update(key, |r| {
+            r.stars = (r.stars as i8 + delta).clamp(0, MAX_STARS as i8) as u8;
+        });
+    }
+}
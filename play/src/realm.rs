@@ -0,0 +1,845 @@
+// Read-only reader for osu!lazer's client.realm.
+// It only understands the subset of the Realm Core file format (file format
+// 20 and later, classic array encodings, no encryption) that lazer writes, which
+// is enough to walk BeatmapCollection -> Beatmap -> BeatmapSet.Files without dotnet.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io,
+    path::Path,
+};
+
+use crate::{FileLayout, JsonItem, Metadata, beatmap};
+
+const HEADER_SIZE: usize = 8;
+const STREAMING_MAGIC: u64 = 0x3034125237E526C8;
+
+// Array header width types
+const WTYPE_BITS: u8 = 0;
+const WTYPE_MULTIPLY: u8 = 1;
+const WTYPE_IGNORE: u8 = 2;
+
+// Cluster inner nodes address 256 keys per level when their key array is compact
+const CLUSTER_NODE_SHIFT: u32 = 8;
+// Far deeper than any real tree, only there to stop on reference cycles
+const MAX_TREE_DEPTH: usize = 32;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+enum RefOrTagged {
+    Ref(usize),
+    Tagged(u64),
+}
+
+struct Node<'a> {
+    data: &'a [u8],
+    size: usize,
+    width: usize,
+    is_inner: bool,
+    context_flag: bool,
+    wtype: u8,
+}
+
+impl Node<'_> {
+    fn bytes(&self, start: usize, len: usize) -> io::Result<&[u8]> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or_else(|| invalid(format!("element {} is out of its array", start)))
+    }
+
+    fn get(&self, i: usize) -> io::Result<i64> {
+        Ok(match self.width {
+            0 => 0,
+            1 | 2 | 4 => {
+                let bit = i * self.width;
+                ((self.bytes(bit / 8, 1)?[0] >> (bit % 8)) & ((1 << self.width) - 1)) as i64
+            }
+            8 => self.bytes(i, 1)?[0] as i8 as i64,
+            16 => i16::from_le_bytes(self.bytes(i * 2, 2)?.try_into().unwrap()) as i64,
+            32 => i32::from_le_bytes(self.bytes(i * 4, 4)?.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(self.bytes(i * 8, 8)?.try_into().unwrap()),
+        })
+    }
+
+    fn get_unsigned(&self, i: usize) -> io::Result<u64> {
+        Ok(match self.width {
+            8 => self.bytes(i, 1)?[0] as u64,
+            16 => u16::from_le_bytes(self.bytes(i * 2, 2)?.try_into().unwrap()) as u64,
+            32 => u32::from_le_bytes(self.bytes(i * 4, 4)?.try_into().unwrap()) as u64,
+            _ => self.get(i)? as u64,
+        })
+    }
+
+    /// Reads an element of a double column leaf (8 bytes per element, null is a NaN)
+    fn get_double(&self, i: usize) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(
+            self.bytes(i * 8, 8)?.try_into().unwrap(),
+        ))
+    }
+
+    fn get_ref_or_tagged(&self, i: usize) -> io::Result<RefOrTagged> {
+        let v = self.get(i)? as u64;
+        Ok(if v & 1 == 1 {
+            RefOrTagged::Tagged(v >> 1)
+        } else {
+            RefOrTagged::Ref(v as usize)
+        })
+    }
+
+    fn get_ref(&self, i: usize) -> io::Result<usize> {
+        Ok(match self.get_ref_or_tagged(i)? {
+            RefOrTagged::Ref(r) => r,
+            RefOrTagged::Tagged(_) => 0,
+        })
+    }
+
+    /// Reads an element of an `ArrayStringShort` (fixed width, padding count in the last byte)
+    fn get_short_string(&self, i: usize) -> io::Result<String> {
+        if self.width == 0 {
+            return Ok(String::new());
+        }
+        let elem = self.bytes(i * self.width, self.width)?;
+        let padding = elem[self.width - 1] as usize;
+        if padding >= self.width {
+            // null
+            return Ok(String::new());
+        }
+        Ok(String::from_utf8_lossy(&elem[..self.width - 1 - padding]).into_owned())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Row {
+    cluster: usize,
+    index: usize,
+}
+
+struct Table<'a> {
+    realm: &'a Realm,
+    columns: HashMap<String, usize>,
+    rows: Vec<Row>,
+    keys: HashMap<i64, usize>,
+}
+
+struct Realm {
+    buf: Vec<u8>,
+    top_ref: usize,
+}
+
+impl Realm {
+    fn open(path: &Path) -> io::Result<Self> {
+        Self::parse(std::fs::read(path)?)
+    }
+
+    fn parse(buf: Vec<u8>) -> io::Result<Self> {
+        if buf.len() < 24 || &buf[16..20] != b"T-DB" {
+            return Err(invalid("not a realm file"));
+        }
+        let read_u64 = |pos: usize| u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap());
+        let select = (buf[23] & 1) as usize;
+        let file_format = buf[20 + select];
+        if file_format < 20 {
+            return Err(invalid(format!(
+                "realm file format {} is too old",
+                file_format
+            )));
+        }
+        let top_ref = if read_u64(0) == u64::MAX {
+            // streaming form: top ref lives in the footer
+            let footer = buf.len() - 16;
+            if read_u64(footer + 8) != STREAMING_MAGIC {
+                return Err(invalid("bad realm streaming footer"));
+            }
+            read_u64(footer)
+        } else {
+            read_u64(select * 8)
+        };
+        let top_ref = usize::try_from(top_ref).map_err(|_| invalid("bad realm top ref"))?;
+        Ok(Self { buf, top_ref })
+    }
+
+    fn node(&self, r: usize) -> io::Result<Node<'_>> {
+        let header = match r.checked_add(HEADER_SIZE) {
+            Some(end) if r != 0 => self.buf.get(r..end),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(format!("bad ref {}", r)))?;
+        let flags = header[4];
+        let wtype = (flags & 0x18) >> 3;
+        let width = (1usize << (flags & 0x07)) >> 1;
+        let size = ((header[5] as usize) << 16) | ((header[6] as usize) << 8) | header[7] as usize;
+        // size is at most 24 bits and width at most 64, so these cannot overflow
+        let byte_size = match wtype {
+            WTYPE_BITS => (size * width).div_ceil(8),
+            WTYPE_MULTIPLY => size * width,
+            WTYPE_IGNORE => size,
+            _ => return Err(invalid("compressed realm arrays are not supported")),
+        };
+        let start = r + HEADER_SIZE;
+        let data = self
+            .buf
+            .get(start..start + byte_size)
+            .ok_or_else(|| invalid(format!("array at {} exceeds file", r)))?;
+        Ok(Node {
+            data,
+            size,
+            width,
+            is_inner: flags & 0x80 != 0,
+            context_flag: flags & 0x20 != 0,
+            wtype,
+        })
+    }
+
+    fn table(&self, name: &str) -> io::Result<Table<'_>> {
+        let top = self.node(self.top_ref)?;
+        let names = self.node(top.get_ref(0)?)?;
+        let tables = self.node(top.get_ref(1)?)?;
+        let mut idx = None;
+        for i in 0..names.size {
+            if names.get_short_string(i)? == name {
+                idx = Some(i);
+                break;
+            }
+        }
+        let idx = idx.ok_or_else(|| invalid(format!("table {} not found", name)))?;
+        let table_top = self.node(tables.get_ref(idx)?)?;
+
+        let spec = self.node(table_top.get_ref(0)?)?;
+        let col_names = self.node(spec.get_ref(1)?)?;
+        let col_keys = self.node(spec.get_ref(5)?)?;
+        let mut columns = HashMap::new();
+        for i in 0..col_names.size {
+            // the low 16 bits of a column key is its position in the cluster
+            let leaf = (col_keys.get(i)? & 0xFFFF) as usize;
+            columns.insert(col_names.get_short_string(i)?, leaf);
+        }
+
+        let mut table = Table {
+            realm: self,
+            columns,
+            rows: Vec::new(),
+            keys: HashMap::new(),
+        };
+        let clusters = table_top.get_ref(2)?;
+        if clusters != 0 {
+            self.collect_rows(clusters, 0, 0, &mut table)?;
+        }
+        Ok(table)
+    }
+
+    fn collect_rows(
+        &self,
+        r: usize,
+        offset: i64,
+        level: usize,
+        table: &mut Table,
+    ) -> io::Result<()> {
+        // a corrupt file could link a node to itself
+        if level > MAX_TREE_DEPTH {
+            return Err(invalid("cluster tree is too deep"));
+        }
+        let node = self.node(r)?;
+        if node.is_inner {
+            // [keys, depth, subtree size, children...]
+            let keys = match node.get_ref_or_tagged(0)? {
+                RefOrTagged::Ref(k) => Some(self.node(k)?),
+                RefOrTagged::Tagged(_) => None,
+            };
+            let depth = node.get(1)? >> 1;
+            for i in 3..node.size {
+                let child = i - 3;
+                let key = match &keys {
+                    Some(k) => k.get_unsigned(child)? as i64,
+                    None => u32::try_from(depth)
+                        .ok()
+                        .and_then(|depth| depth.checked_mul(CLUSTER_NODE_SHIFT))
+                        .and_then(|shift| (child as i64).checked_shl(shift))
+                        .ok_or_else(|| invalid("bad cluster depth"))?,
+                };
+                self.collect_rows(node.get_ref(i)?, offset.wrapping_add(key), level + 1, table)?;
+            }
+        } else {
+            // [keys, column leaves...]
+            match node.get_ref_or_tagged(0)? {
+                RefOrTagged::Tagged(n) => {
+                    for i in 0..n as usize {
+                        table.push_row(offset.wrapping_add(i as i64), r, i);
+                    }
+                }
+                RefOrTagged::Ref(k) => {
+                    let keys = self.node(k)?;
+                    for i in 0..keys.size {
+                        table.push_row(offset.wrapping_add(keys.get_unsigned(i)? as i64), r, i);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the leaves of a B+tree (used by lists)
+    fn bptree_leaves(&self, r: usize, level: usize, out: &mut Vec<usize>) -> io::Result<()> {
+        if level > MAX_TREE_DEPTH {
+            return Err(invalid("list tree is too deep"));
+        }
+        let node = self.node(r)?;
+        if node.is_inner {
+            // [offsets, children..., total size]
+            for i in 1..node.size.saturating_sub(1) {
+                self.bptree_leaves(node.get_ref(i)?, level + 1, out)?;
+            }
+        } else {
+            out.push(r);
+        }
+        Ok(())
+    }
+
+    fn string(&self, leaf: &Node, i: usize) -> io::Result<String> {
+        if leaf.wtype == WTYPE_MULTIPLY {
+            return leaf.get_short_string(i);
+        }
+        if !leaf.context_flag {
+            // medium strings: [end offsets, blob, nulls]
+            let offsets = self.node(leaf.get_ref(0)?)?;
+            let blob = self.node(leaf.get_ref(1)?)?;
+            let begin = if i == 0 {
+                0
+            } else {
+                offsets.get(i - 1)? as usize
+            };
+            let end = offsets.get(i)? as usize;
+            if end <= begin {
+                return Ok(String::new());
+            }
+            // strip the terminating zero
+            let bytes = blob
+                .data
+                .get(begin..end - 1)
+                .ok_or_else(|| invalid(format!("string {} exceeds its blob", i)))?;
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        } else {
+            // big strings: one blob per string, 0 means null
+            let r = leaf.get_ref(i)?;
+            if r == 0 {
+                return Ok(String::new());
+            }
+            let blob = self.node(r)?;
+            let bytes = blob
+                .data
+                .get(..blob.size.saturating_sub(1))
+                .ok_or_else(|| invalid(format!("string {} exceeds its blob", i)))?;
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+}
+
+impl<'a> Table<'a> {
+    fn push_row(&mut self, key: i64, cluster: usize, index: usize) {
+        self.keys.insert(key, self.rows.len());
+        self.rows.push(Row { cluster, index });
+    }
+
+    fn row(&self, key: i64) -> Option<Row> {
+        self.keys.get(&key).map(|&i| self.rows[i])
+    }
+
+    fn column(&self, row: Row, name: &str) -> io::Result<Node<'a>> {
+        let leaf = *self
+            .columns
+            .get(name)
+            .ok_or_else(|| invalid(format!("column {} not found", name)))?;
+        let cluster = self.realm.node(row.cluster)?;
+        self.realm.node(cluster.get_ref(leaf + 1)?)
+    }
+
+    fn string(&self, row: Row, name: &str) -> io::Result<String> {
+        let leaf = self.column(row, name)?;
+        self.realm.string(&leaf, row.index)
+    }
+
+    fn double(&self, row: Row, name: &str) -> io::Result<Option<f64>> {
+        let v = self.column(row, name)?.get_double(row.index)?;
+        Ok(v.is_finite().then_some(v))
+    }
+
+    fn link(&self, row: Row, name: &str) -> io::Result<Option<i64>> {
+        // links are stored as key + 1, so that 0 means null
+        let v = self.column(row, name)?.get(row.index)?;
+        Ok(v.checked_sub(1).filter(|&k| k >= 0))
+    }
+
+    fn list_leaves(&self, row: Row, name: &str) -> io::Result<Vec<usize>> {
+        let mut leaves = Vec::new();
+        let r = self.column(row, name)?.get_ref(row.index)?;
+        if r != 0 {
+            self.realm.bptree_leaves(r, 0, &mut leaves)?;
+        }
+        Ok(leaves)
+    }
+
+    fn link_list(&self, row: Row, name: &str) -> io::Result<Vec<i64>> {
+        let mut result = Vec::new();
+        for r in self.list_leaves(row, name)? {
+            let leaf = self.realm.node(r)?;
+            for i in 0..leaf.size {
+                // unresolved links (tombstones) end up negative here
+                if let Some(k) = leaf.get(i)?.checked_sub(1).filter(|&k| k >= 0) {
+                    result.push(k);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn string_list(&self, row: Row, name: &str) -> io::Result<Vec<String>> {
+        let mut result = Vec::new();
+        for r in self.list_leaves(row, name)? {
+            let leaf = self.realm.node(r)?;
+            for i in 0..leaf.size {
+                result.push(self.realm.string(&leaf, i)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+struct SetEntry {
+    set_key: i64,
+    audio_hash: String,
+    bg_hashes: Vec<String>,
+    metadata: Metadata,
+    tags: Vec<String>,
+    osu_hashes: Vec<String>,
+}
+
+/// Maps lowercased file names of a beatmap set to their hashes
+fn read_set_files(
+    sets: &Table,
+    usages: &Table,
+    files: &Table,
+    set: Row,
+) -> io::Result<HashMap<String, String>> {
+    let mut result = HashMap::new();
+    for usage in sets.link_list(set, "Files")? {
+        let Some(usage) = usages.row(usage) else {
+            continue;
+        };
+        let Some(file) = usages.link(usage, "File")?.and_then(|k| files.row(k)) else {
+            continue;
+        };
+        let hash = files.string(file, "Hash")?;
+        if hash.is_empty() {
+            continue;
+        }
+        result.insert(usages.string(usage, "Filename")?.to_lowercase(), hash);
+    }
+    Ok(result)
+}
+
+fn merge_field(field: &mut String, other: String) {
+    if field.is_empty() {
+        *field = other;
+    }
+}
+
+/// Reads songs in `collections` (or in any collection if empty) from client.realm,
+/// merging difficulties of the same beatmap set like RealmHashExtractor does.
+pub fn load_songs(
+    realm_path: &Path,
+    files_path: &Path,
+    collections: &[String],
+    beatmap_cache: &mut beatmap::Cache,
+) -> io::Result<Vec<JsonItem>> {
+    read_songs(
+        &Realm::open(realm_path)?,
+        files_path,
+        collections,
+        beatmap_cache,
+    )
+}
+
+fn read_songs(
+    realm: &Realm,
+    files_path: &Path,
+    collections: &[String],
+    beatmap_cache: &mut beatmap::Cache,
+) -> io::Result<Vec<JsonItem>> {
+    let collection_table = realm.table("class_BeatmapCollection")?;
+    let beatmaps = realm.table("class_Beatmap")?;
+    let metadatas = realm.table("class_BeatmapMetadata")?;
+    let sets = realm.table("class_BeatmapSet")?;
+    let usages = realm.table("class_RealmNamedFileUsage")?;
+    let files = realm.table("class_File")?;
+
+    let mut by_md5: HashMap<String, Vec<Row>> = HashMap::new();
+    for &row in &beatmaps.rows {
+        by_md5
+            .entry(beatmaps.string(row, "MD5Hash")?)
+            .or_default()
+            .push(row);
+    }
+
+    let mut set_index: HashMap<i64, usize> = HashMap::new();
+    let mut set_files: HashMap<i64, HashMap<String, String>> = HashMap::new();
+    let mut entries: Vec<SetEntry> = Vec::new();
+    for &collection in &collection_table.rows {
+        let name = collection_table.string(collection, "Name")?;
+        if !collections.is_empty() && !collections.contains(&name) {
+            continue;
+        }
+        for hash in collection_table.string_list(collection, "BeatmapMD5Hashes")? {
+            for &beatmap in by_md5.get(&hash).into_iter().flatten() {
+                let Some(set_key) = beatmaps.link(beatmap, "BeatmapSet")? else {
+                    continue;
+                };
+                let (Some(set), Some(metadata)) = (
+                    sets.row(set_key),
+                    beatmaps
+                        .link(beatmap, "Metadata")?
+                        .and_then(|k| metadatas.row(k)),
+                ) else {
+                    continue;
+                };
+                let set_files = match set_files.entry(set_key) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => e.insert(read_set_files(&sets, &usages, &files, set)?),
+                };
+                // file names are case-insensitive
+                let audio_name = metadatas.string(metadata, "AudioFile")?.to_lowercase();
+                let bg_name = metadatas.string(metadata, "BackgroundFile")?.to_lowercase();
+                let Some(audio_hash) = set_files.get(&audio_name).cloned() else {
+                    continue;
+                };
+                let bg_hash = set_files.get(&bg_name).cloned();
+                // the hash of a beatmap is the hash of its .osu file
+                let osu_hash = beatmaps.string(beatmap, "Hash")?;
+                // in milliseconds, optional as it is not needed to play
+                let length = beatmaps.double(beatmap, "Length").ok().flatten();
+
+                let title = metadatas.string(metadata, "Title")?;
+                let title_unicode = metadatas.string(metadata, "TitleUnicode")?;
+                let artist = metadatas.string(metadata, "Artist")?;
+                let artist_unicode = metadatas.string(metadata, "ArtistUnicode")?;
+                let source = metadatas.string(metadata, "Source")?;
+                let tags = metadatas.string(metadata, "Tags")?;
+
+                let entry = match set_index.get(&set_key) {
+                    Some(&i) => &mut entries[i],
+                    None => {
+                        set_index.insert(set_key, entries.len());
+                        entries.push(SetEntry {
+                            set_key,
+                            audio_hash,
+                            bg_hashes: Vec::new(),
+                            metadata: Metadata::default(),
+                            tags: Vec::new(),
+                            osu_hashes: Vec::new(),
+                        });
+                        entries.last_mut().unwrap()
+                    }
+                };
+                merge_field(&mut entry.metadata.title, title);
+                merge_field(&mut entry.metadata.title_unicode, title_unicode);
+                merge_field(&mut entry.metadata.artist, artist);
+                merge_field(&mut entry.metadata.artist_unicode, artist_unicode);
+                merge_field(&mut entry.metadata.source, source);
+                for tag in tags.split_whitespace() {
+                    if !entry.tags.iter().any(|t| t == tag) {
+                        entry.tags.push(tag.to_string());
+                    }
+                }
+                if let Some(bg_hash) = bg_hash
+                    && !entry.bg_hashes.contains(&bg_hash)
+                {
+                    entry.bg_hashes.push(bg_hash);
+                }
+                if let Some(length) = length.filter(|&l| l > 0.0) {
+                    let length = length as u32;
+                    entry.metadata.length =
+                        Some(entry.metadata.length.map_or(length, |l| l.max(length)));
+                }
+                if !entry.metadata.collections.contains(&name) {
+                    entry.metadata.collections.push(name.clone());
+                }
+                if !osu_hash.is_empty() && !entry.osu_hashes.contains(&osu_hash) {
+                    entry.osu_hashes.push(osu_hash);
+                }
+            }
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|mut e| {
+            e.metadata.tags = e.tags.join(" ");
+            let mut item = JsonItem {
+                audio_hash: e.audio_hash,
+                bg_hashes: e.bg_hashes,
+                metadata: e.metadata,
+                layout: FileLayout::Hashed(files_path.to_path_buf()),
+            };
+            let osu_files: Vec<_> = e
+                .osu_hashes
+                .iter()
+                .map(|hash| item.get_file_path(hash))
+                .collect();
+            let set_files = &set_files[&e.set_key];
+            beatmap_cache.enrich(&mut item, &osu_files, |name| {
+                set_files.get(&name.to_lowercase()).cloned()
+            });
+            item
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(value: u64) -> u64 {
+        (value << 1) | 1
+    }
+
+    /// Lays out arrays like Realm Core does, to build small realm files
+    struct Builder {
+        buf: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            let mut buf = vec![0; 24];
+            buf[16..20].copy_from_slice(b"T-DB");
+            buf[20] = 22;
+            buf[21] = 22;
+            Self { buf }
+        }
+
+        /// An array of 64 bit elements, `flags` being the inner and has refs bits
+        fn array(&mut self, flags: u8, values: &[u64]) -> u64 {
+            let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            self.push(flags | 7, values.len(), &data)
+        }
+
+        fn refs(&mut self, refs: &[u64]) -> u64 {
+            self.array(0x40, refs)
+        }
+
+        fn ints(&mut self, values: &[i64]) -> u64 {
+            self.array(0, &values.iter().map(|&v| v as u64).collect::<Vec<_>>())
+        }
+
+        fn doubles(&mut self, values: &[f64]) -> u64 {
+            self.array(0, &values.iter().map(|v| v.to_bits()).collect::<Vec<_>>())
+        }
+
+        /// An `ArrayStringShort` with 32 bytes per element
+        fn strings(&mut self, values: &[&str]) -> u64 {
+            let mut data = Vec::new();
+            for value in values {
+                assert!(value.len() < 32);
+                data.extend_from_slice(value.as_bytes());
+                data.resize(data.len() + 31 - value.len(), 0);
+                data.push((31 - value.len()) as u8);
+            }
+            self.push((WTYPE_MULTIPLY << 3) | 6, values.len(), &data)
+        }
+
+        fn push(&mut self, flags: u8, size: usize, data: &[u8]) -> u64 {
+            let r = self.buf.len();
+            self.buf.extend_from_slice(&[b'A', b'A', b'A', b'A', flags]);
+            self.buf
+                .extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+            self.buf.extend_from_slice(data);
+            // refs stay 8 byte aligned, keeping their lowest bit for tagged ints
+            self.buf.resize(self.buf.len().next_multiple_of(8), 0);
+            r as u64
+        }
+
+        /// A table of `rows` rows in a single cluster, from its columns' leaves
+        fn table(&mut self, rows: u64, columns: &[(&str, u64)]) -> u64 {
+            let names: Vec<_> = columns.iter().map(|&(name, _)| name).collect();
+            let names = self.strings(&names);
+            let keys: Vec<_> = (0..columns.len() as i64).collect();
+            let keys = self.ints(&keys);
+            // [types, names, attributes, sub specs, enum keys, keys]
+            let spec = self.refs(&[tag(0), names, tag(0), tag(0), tag(0), keys]);
+            let mut cluster = vec![tag(rows)];
+            cluster.extend(columns.iter().map(|&(_, leaf)| leaf));
+            let cluster = self.refs(&cluster);
+            self.refs(&[spec, tag(0), cluster])
+        }
+
+        fn finish(mut self, tables: &[(&str, u64)]) -> Vec<u8> {
+            let names: Vec<_> = tables.iter().map(|&(name, _)| name).collect();
+            let names = self.strings(&names);
+            let tables: Vec<_> = tables.iter().map(|&(_, table)| table).collect();
+            let tables = self.refs(&tables);
+            let top = self.refs(&[names, tables]);
+            self.buf[..8].copy_from_slice(&top.to_le_bytes());
+            self.buf
+        }
+    }
+
+    /// A beatmap set with two difficulties in collection "Favourites", which
+    /// lists its beatmaps with `favourites`
+    fn fixture(favourites: impl FnOnce(&mut Builder) -> u64) -> Vec<u8> {
+        let mut b = Builder::new();
+
+        let leaf = b.strings(&["0aud10", "0b9", "0d1ff1"]);
+        let files = b.table(3, &[("Hash", leaf)]);
+
+        let file = b.ints(&[1, 2, 3]);
+        let filename = b.strings(&["Audio.MP3", "bg.jpg", "song (Hard).osu"]);
+        let usages = b.table(3, &[("File", file), ("Filename", filename)]);
+
+        // the trailing 0 is a link to a deleted usage
+        let set_files = b.ints(&[1, 2, 3, 0]);
+        let set_files = b.refs(&[set_files]);
+        let sets = b.table(1, &[("Files", set_files)]);
+
+        let mut metadata = Vec::new();
+        for (name, value) in [
+            ("AudioFile", "audio.mp3"),
+            ("BackgroundFile", "BG.jpg"),
+            ("Title", "Song"),
+            ("TitleUnicode", "歌"),
+            ("Artist", "Artist"),
+            ("ArtistUnicode", ""),
+            ("Source", ""),
+            ("Tags", " tag1  tag2 tag1 "),
+        ] {
+            metadata.push((name, b.strings(&[value])));
+        }
+        let metadatas = b.table(1, &metadata);
+
+        let md5 = b.strings(&["md5a", "md5b"]);
+        let set = b.ints(&[1, 1]);
+        let metadata = b.ints(&[1, 1]);
+        let hash = b.strings(&["0d1ff1", "0d1ff2"]);
+        let length = b.doubles(&[90000.0, 120000.0]);
+        let beatmaps = b.table(
+            2,
+            &[
+                ("MD5Hash", md5),
+                ("BeatmapSet", set),
+                ("Metadata", metadata),
+                ("Hash", hash),
+                ("Length", length),
+            ],
+        );
+
+        let name = b.strings(&["Favourites", "Empty"]);
+        let favourites = favourites(&mut b);
+        let hashes = b.refs(&[favourites, 0]);
+        let collections = b.table(2, &[("Name", name), ("BeatmapMD5Hashes", hashes)]);
+
+        b.finish(&[
+            ("class_File", files),
+            ("class_RealmNamedFileUsage", usages),
+            ("class_BeatmapSet", sets),
+            ("class_BeatmapMetadata", metadatas),
+            ("class_Beatmap", beatmaps),
+            ("class_BeatmapCollection", collections),
+        ])
+    }
+
+    fn both_difficulties(b: &mut Builder) -> u64 {
+        b.strings(&["md5a", "md5b"])
+    }
+
+    fn songs(buf: Vec<u8>, collections: &[&str]) -> io::Result<Vec<JsonItem>> {
+        let collections: Vec<_> = collections.iter().map(|c| c.to_string()).collect();
+        read_songs(
+            &Realm::parse(buf)?,
+            Path::new("/nonexistent"),
+            &collections,
+            &mut beatmap::Cache::default(),
+        )
+    }
+
+    #[test]
+    fn reads_songs() {
+        let songs = songs(fixture(both_difficulties), &[]).unwrap();
+        assert_eq!(songs.len(), 1);
+        let song = &songs[0];
+        assert_eq!(song.audio_hash, "0aud10");
+        assert_eq!(song.bg_hashes, ["0b9"]);
+        assert_eq!(song.metadata.title, "Song");
+        assert_eq!(song.metadata.title_unicode, "歌");
+        assert_eq!(song.metadata.artist, "Artist");
+        assert_eq!(song.metadata.tags, "tag1 tag2");
+        assert_eq!(song.metadata.length, Some(120000));
+        assert_eq!(song.metadata.collections, ["Favourites"]);
+    }
+
+    #[test]
+    fn filters_collections() {
+        assert_eq!(
+            songs(fixture(both_difficulties), &["Favourites"])
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            songs(fixture(both_difficulties), &["Empty"])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reads_list_through_inner_nodes() {
+        let songs = songs(
+            fixture(|b| {
+                let first = b.strings(&["md5a"]);
+                let second = b.strings(&["md5b"]);
+                // [offsets, children..., total size]
+                b.array(0xC0, &[tag(0), first, second, tag(2)])
+            }),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].audio_hash, "0aud10");
+    }
+
+    #[test]
+    fn empty_inner_node_is_an_empty_list() {
+        let songs = songs(fixture(|b| b.array(0xC0, &[])), &[]).unwrap();
+        assert!(songs.is_empty());
+    }
+
+    #[test]
+    fn rejects_reference_cycle() {
+        let buf = fixture(|b| {
+            let r = b.buf.len() as u64;
+            b.array(0xC0, &[tag(0), r, tag(1)])
+        });
+        assert!(songs(buf, &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_short_column() {
+        let mut b = Builder::new();
+        // a Hash column with only one element for the two beatmaps
+        let md5 = b.strings(&["md5a", "md5b"]);
+        let hash = b.strings(&["0d1ff1"]);
+        let beatmaps = b.table(2, &[("MD5Hash", md5), ("Hash", hash)]);
+        let buf = b.finish(&[("class_Beatmap", beatmaps)]);
+        let realm = Realm::parse(buf).unwrap();
+        let table = realm.table("class_Beatmap").unwrap();
+        assert_eq!(table.string(table.rows[0], "Hash").unwrap(), "0d1ff1");
+        assert!(table.string(table.rows[1], "Hash").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let buf = fixture(both_difficulties);
+        for len in 0..buf.len() {
+            assert!(songs(buf[..len].to_vec(), &[]).is_err(), "length {}", len);
+        }
+    }
+}
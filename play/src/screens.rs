@@ -0,0 +1,921 @@
+// Screens for picking from a list: queue, history, library browser, playlists,
+// collections, cue points and audio devices. Each has one list, or a column of
+// groups next to the songs of the selected one, above a line of key hints.
+
+use std::{sync::mpsc, time::Duration};
+
+use crossterm::event;
+use ratatui::{
+    Frame, Terminal,
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, Borders, ListItem, ListState, Paragraph},
+};
+use ratatui_image::picker::Picker;
+use tui_input::{Input, backend::crossterm::EventHandler};
+
+use crate::{
+    App, Grouping, InternalControl, NamePrompt, UIState, format_ago, navigate_list, song_list,
+};
+
+/// A list on screen
+struct Column {
+    title: String,
+    items: Vec<ListItem<'static>>,
+    /// Highlighted border, for the column with focus when there are two
+    focused: bool,
+}
+
+/// Line below the lists
+enum Footer {
+    /// Key hints, unless there is a message to show instead
+    Hint(&'static str),
+    /// Name being typed, after a label
+    Prompt {
+        label: &'static str,
+        value: String,
+        cursor: usize,
+    },
+}
+
+impl Footer {
+    fn prompt(label: &'static str, input: &Input) -> Self {
+        Footer::Prompt {
+            label,
+            value: input.value().to_string(),
+            cursor: input.visual_cursor(),
+        }
+    }
+
+    fn draw(self, frame: &mut Frame, area: Rect, message: &str) {
+        match self {
+            Footer::Hint(hint) => {
+                let text = if message.is_empty() { hint } else { message };
+                frame.render_widget(Paragraph::new(text), area);
+            }
+            Footer::Prompt {
+                label,
+                value,
+                cursor,
+            } => {
+                frame.render_widget(
+                    Paragraph::new(format!("{}{}", label, value))
+                        .style(Style::default().fg(Color::Yellow)),
+                    area,
+                );
+                frame.set_cursor_position((area.x + (label.len() + cursor) as u16, area.y));
+            }
+        }
+    }
+}
+
+/// Draws a screen of one list, or two side by side, above the footer.
+/// Returns the number of rows the lists show, for paging.
+fn draw_screen<B: Backend>(
+    terminal: &mut Terminal<B>,
+    title: &str,
+    columns: Vec<(Column, &mut ListState)>,
+    footer: Footer,
+    message: &str,
+) -> u16 {
+    let mut height = 1;
+    terminal
+        .draw(|frame| {
+            let outer_block = Block::default().title(title).borders(Borders::TOP);
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints([Constraint::Min(3), Constraint::Length(1)])
+                .split(outer_block.inner(frame.area()));
+            frame.render_widget(outer_block, frame.area());
+            let widths = match columns.len() {
+                1 => vec![Constraint::Percentage(100)],
+                _ => vec![Constraint::Percentage(35), Constraint::Percentage(65)],
+            };
+            let areas = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(widths)
+                .split(rows[0]);
+            for ((column, list_state), &area) in columns.into_iter().zip(areas.iter()) {
+                let border_style = if column.focused {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default()
+                };
+                let list = song_list(column.items, String::new()).block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(column.title)
+                        .border_style(border_style),
+                );
+                frame.render_stateful_widget(list, area, list_state);
+            }
+            footer.draw(frame, rows[1], message);
+            height = (rows[0].height - 2).max(1);
+        })
+        .unwrap();
+    height
+}
+
+/// Waits briefly for a key. Keys and resizes have the screen drawn again, and keys clear the message.
+fn read_key(app: &mut App) -> Option<event::KeyEvent> {
+    if !event::poll(Duration::from_millis(16)).unwrap() {
+        return None;
+    }
+    match event::read().unwrap() {
+        event::Event::Key(key_event) => {
+            app.ui_dirty = true;
+            app.message.clear();
+            Some(key_event)
+        }
+        event::Event::Resize(_, _) => {
+            app.ui_dirty = true;
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Types a key into the name prompt. Enter takes the prompt out and gives it back, Esc drops it.
+fn type_name(
+    prompt: &mut Option<(NamePrompt, Input)>,
+    key_event: event::KeyEvent,
+) -> Option<(NamePrompt, Input)> {
+    let (kind, mut input) = prompt.take()?;
+    match key_event.code {
+        event::KeyCode::Esc => None,
+        event::KeyCode::Enter => Some((kind, input)),
+        _ => {
+            input.handle_event(&event::Event::Key(key_event));
+            *prompt = Some((kind, input));
+            None
+        }
+    }
+}
+
+/// "Title (3/10)" with the position of the selected item
+fn counted(title: &str, list_state: &ListState, total: usize) -> String {
+    match list_state.selected() {
+        Some(i) => format!("{} ({}/{})", title, i + 1, total),
+        None => title.to_string(),
+    }
+}
+
+pub fn queue_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+    picker: &Picker,
+) {
+    if app.ui_dirty {
+        let items: Vec<ListItem> = app
+            .queue
+            .iter()
+            .map(|&i| ListItem::new(app.item_to_string(i)))
+            .collect();
+        let list = Column {
+            title: counted("Up next", &app.queue_state.list_state, items.len()),
+            items,
+            focused: false,
+        };
+        app.queue_state.list_height = draw_screen(
+            terminal,
+            "Queue",
+            vec![(list, &mut app.queue_state.list_state)],
+            Footer::Hint(
+                "Enter: play now  Shift+Up/Down: move  d: remove  c: clear  e: export  Esc: back",
+            ),
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    let state = &mut app.queue_state;
+    let selected = state.list_state.selected().filter(|&i| i < app.queue.len());
+    if key_event.modifiers.contains(event::KeyModifiers::SHIFT)
+        && matches!(key_event.code, event::KeyCode::Up | event::KeyCode::Down)
+    {
+        if let Some(i) = selected {
+            let j = if key_event.code == event::KeyCode::Up {
+                i.saturating_sub(1)
+            } else {
+                (i + 1).min(app.queue.len() - 1)
+            };
+            app.queue.swap(i, j);
+            state.list_state.select(Some(j));
+        }
+        return;
+    }
+    let page = state.list_height.into();
+    if navigate_list(&mut state.list_state, app.queue.len(), page, key_event.code) {
+        return;
+    }
+    match key_event.code {
+        event::KeyCode::Delete | event::KeyCode::Char('d') => {
+            if let Some(i) = selected {
+                app.queue.remove(i);
+                if app.queue.is_empty() {
+                    app.queue_state.list_state.select(None);
+                } else {
+                    app.queue_state
+                        .list_state
+                        .select(Some(i.min(app.queue.len() - 1)));
+                }
+            }
+        }
+        event::KeyCode::Char('c') => {
+            app.queue.clear();
+            app.queue_state.list_state.select(None);
+        }
+        event::KeyCode::Char('e') => {
+            let queue: Vec<usize> = app.queue.iter().copied().collect();
+            app.export("queue", &queue);
+        }
+        event::KeyCode::Enter => {
+            if let Some(i) = selected {
+                let idx = app.queue.remove(i).unwrap();
+                app.play_idx(idx, mpv_control_tx.clone(), picker);
+                app.queue_state.list_state.select(None);
+                app.ui_state = UIState::Main;
+            }
+        }
+        event::KeyCode::Char('u') => {
+            app.toggle_unicode();
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('l') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn history_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+    picker: &Picker,
+) {
+    if app.ui_dirty {
+        // most recent first
+        let items: Vec<ListItem> = app
+            .history
+            .iter()
+            .rev()
+            .map(|e| {
+                ListItem::new(format!(
+                    "{} ({})",
+                    app.item_to_string(e.idx),
+                    format_ago(e.played_at)
+                ))
+            })
+            .collect();
+        let list = Column {
+            title: counted(
+                "Recently played",
+                &app.history_state.list_state,
+                items.len(),
+            ),
+            items,
+            focused: false,
+        };
+        app.history_state.list_height = draw_screen(
+            terminal,
+            "History",
+            vec![(list, &mut app.history_state.list_state)],
+            Footer::Hint("Enter: play again  a: add to queue  Esc: back"),
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    let state = &mut app.history_state;
+    let page = state.list_height.into();
+    if navigate_list(
+        &mut state.list_state,
+        app.history.len(),
+        page,
+        key_event.code,
+    ) {
+        return;
+    }
+    let selected = state
+        .list_state
+        .selected()
+        .filter(|&i| i < app.history.len())
+        .map(|i| app.history[app.history.len() - 1 - i].idx);
+    match key_event.code {
+        event::KeyCode::Enter => {
+            if let Some(idx) = selected {
+                app.play_idx(idx, mpv_control_tx.clone(), picker);
+                app.ui_state = UIState::Main;
+            }
+        }
+        event::KeyCode::Char('a') => {
+            if let Some(idx) = selected {
+                app.queue.push_back(idx);
+            }
+        }
+        event::KeyCode::Char('u') => {
+            app.toggle_unicode();
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('h') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn browse_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+    picker: &Picker,
+) {
+    if app.ui_dirty {
+        let state = &app.browse_state;
+        let groups = Column {
+            title: format!("{} ({})", state.grouping.name(), state.groups.len()),
+            items: state
+                .groups
+                .iter()
+                .map(|(name, songs)| ListItem::new(format!("{} ({})", name, songs.len())))
+                .collect(),
+            focused: !state.songs_focused,
+        };
+        let selected_group = state
+            .group_state
+            .selected()
+            .and_then(|i| state.groups.get(i));
+        let songs = Column {
+            title: selected_group
+                .map(|(name, _)| name.clone())
+                .unwrap_or_default(),
+            items: selected_group
+                .map(|(_, songs)| {
+                    songs
+                        .iter()
+                        .map(|&i| ListItem::new(app.item_to_string(i)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            focused: state.songs_focused,
+        };
+        let state = &mut app.browse_state;
+        state.list_height = draw_screen(
+            terminal,
+            "Library",
+            vec![
+                (groups, &mut state.group_state),
+                (songs, &mut state.song_state),
+            ],
+            Footer::Hint(
+                "Tab: group by  Left/Right: switch column  Enter: play  n: play next  a: add to queue  p: add to playlist  Esc: back",
+            ),
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    let state = &mut app.browse_state;
+    let group: Vec<usize> = state
+        .group_state
+        .selected()
+        .and_then(|i| state.groups.get(i))
+        .map(|(_, songs)| songs.clone())
+        .unwrap_or_default();
+    let page = state.list_height.into();
+    if state.songs_focused {
+        if navigate_list(&mut state.song_state, group.len(), page, key_event.code) {
+            return;
+        }
+    } else if navigate_list(
+        &mut state.group_state,
+        state.groups.len(),
+        page,
+        key_event.code,
+    ) {
+        state.song_state.select(None);
+        return;
+    }
+    // the selected song, or the whole group when the group column has focus
+    let selected: Vec<usize> = if state.songs_focused {
+        state
+            .song_state
+            .selected()
+            .and_then(|i| group.get(i))
+            .into_iter()
+            .copied()
+            .collect()
+    } else {
+        group.clone()
+    };
+    match key_event.code {
+        event::KeyCode::Right if !group.is_empty() => {
+            state.songs_focused = true;
+            if state.song_state.selected().is_none() {
+                state.song_state.select(Some(0));
+            }
+        }
+        event::KeyCode::Left => {
+            state.songs_focused = false;
+        }
+        event::KeyCode::Tab => {
+            state.grouping = state.grouping.next();
+            app.build_groups();
+        }
+        event::KeyCode::Enter if !selected.is_empty() => {
+            app.play_group(&selected, mpv_control_tx.clone(), picker);
+            app.ui_state = UIState::Main;
+        }
+        event::KeyCode::Char('n') => {
+            for &i in selected.iter().rev() {
+                app.queue.push_front(i);
+            }
+        }
+        event::KeyCode::Char('a') => {
+            app.queue.extend(selected);
+        }
+        event::KeyCode::Char('p') if !selected.is_empty() => {
+            app.toggle_in_playlist(&selected);
+        }
+        event::KeyCode::Char('u') => {
+            app.toggle_unicode();
+            // artist names depend on unicode mode
+            if app.browse_state.grouping == Grouping::Artist {
+                app.build_groups();
+            }
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('b') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn playlists_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+    picker: &Picker,
+) {
+    if app.ui_dirty {
+        let state = &app.playlist_state;
+        let playlists = Column {
+            title: format!("Playlists ({})", app.playlists.len()),
+            items: app
+                .playlists
+                .iter()
+                .map(|playlist| {
+                    let mut text = format!("{} ({})", playlist.name, playlist.songs.len());
+                    if app.playlist.as_ref() == Some(&playlist.name) {
+                        text.push_str(" playing");
+                    }
+                    if app.target_playlist.as_ref() == Some(&playlist.name) {
+                        text.push_str(" target");
+                    }
+                    ListItem::new(text)
+                })
+                .collect(),
+            focused: !state.songs_focused,
+        };
+        let selected = state
+            .list_state
+            .selected()
+            .and_then(|i| app.playlists.get(i));
+        let songs: Vec<usize> = selected
+            .map(|playlist| app.playlist_songs(playlist))
+            .unwrap_or_default();
+        let songs = Column {
+            title: selected
+                .map(|playlist| {
+                    let missing = playlist.songs.len() - songs.len();
+                    if missing > 0 {
+                        format!("{} ({} not in library)", playlist.name, missing)
+                    } else {
+                        playlist.name.clone()
+                    }
+                })
+                .unwrap_or_default(),
+            items: songs
+                .iter()
+                .map(|&i| ListItem::new(app.item_to_string(i)))
+                .collect(),
+            focused: state.songs_focused,
+        };
+        let footer = match &state.prompt {
+            Some((NamePrompt::Create, input)) => Footer::prompt("New playlist: ", input),
+            Some((NamePrompt::Rename, input)) => Footer::prompt("Rename to: ", input),
+            None => Footer::Hint(
+                "Enter: play  n: new  r: rename  d: delete  t: add songs here  e: export  x: play whole library  Esc: back",
+            ),
+        };
+        let state = &mut app.playlist_state;
+        state.list_height = draw_screen(
+            terminal,
+            "Playlists",
+            vec![
+                (playlists, &mut state.list_state),
+                (songs, &mut state.song_state),
+            ],
+            footer,
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    if app.playlist_state.prompt.is_some() {
+        if let Some((prompt, input)) = type_name(&mut app.playlist_state.prompt, key_event) {
+            let name = input.value().to_string();
+            let done = match prompt {
+                NamePrompt::Create => app.create_playlist(&name),
+                NamePrompt::Rename => app
+                    .playlist_state
+                    .list_state
+                    .selected()
+                    .and_then(|i| app.rename_playlist(i, &name)),
+            };
+            match done {
+                Some(i) => {
+                    app.playlist_state.list_state.select(Some(i));
+                    app.playlist_state.song_state.select(None);
+                }
+                // keep the prompt open to fix the name
+                None => app.playlist_state.prompt = Some((prompt, input)),
+            }
+        }
+        return;
+    }
+    let selected = app
+        .playlist_state
+        .list_state
+        .selected()
+        .filter(|&i| i < app.playlists.len());
+    let songs: Vec<usize> = selected
+        .map(|i| app.playlist_songs(&app.playlists[i]))
+        .unwrap_or_default();
+    let state = &mut app.playlist_state;
+    let deleting = state.deleting.take();
+    let page = state.list_height.into();
+    if state.songs_focused {
+        if navigate_list(&mut state.song_state, songs.len(), page, key_event.code) {
+            return;
+        }
+    } else if navigate_list(
+        &mut state.list_state,
+        app.playlists.len(),
+        page,
+        key_event.code,
+    ) {
+        state.song_state.select(None);
+        return;
+    }
+    let song = state
+        .song_state
+        .selected()
+        .filter(|_| state.songs_focused)
+        .and_then(|i| songs.get(i).copied());
+    match key_event.code {
+        event::KeyCode::Right if !songs.is_empty() => {
+            state.songs_focused = true;
+            if state.song_state.selected().is_none() {
+                state.song_state.select(Some(0));
+            }
+        }
+        event::KeyCode::Left => {
+            state.songs_focused = false;
+        }
+        event::KeyCode::Enter => {
+            if let Some(i) = selected {
+                if app.start_playlist(i, song) {
+                    app.play_idx(app.idx, mpv_control_tx.clone(), picker);
+                    app.ui_state = UIState::Main;
+                } else {
+                    app.message = format!("{} has no songs in the library", app.playlists[i].name);
+                }
+            }
+        }
+        event::KeyCode::Char('n') => {
+            state.prompt = Some((NamePrompt::Create, Input::default()));
+        }
+        event::KeyCode::Char('r') => {
+            if let Some(i) = selected {
+                let input = Input::new(app.playlists[i].name.clone());
+                state.prompt = Some((NamePrompt::Rename, input));
+            }
+        }
+        event::KeyCode::Char('d') => match (selected, song) {
+            (Some(i), Some(idx)) => {
+                let key = &app.json_item[idx].audio_hash;
+                app.playlists[i].songs.retain(|k| k != key);
+                app.save_playlist(i);
+                app.playlist_changed(i);
+                let state = &mut app.playlist_state;
+                if songs.len() <= 1 {
+                    state.songs_focused = false;
+                    state.song_state.select(None);
+                } else if let Some(k) = state.song_state.selected() {
+                    state.song_state.select(Some(k.min(songs.len() - 2)));
+                }
+            }
+            (Some(i), None) if deleting == Some(i) => {
+                app.delete_playlist(i);
+                let state = &mut app.playlist_state;
+                state.list_state.select(match app.playlists.len() {
+                    0 => None,
+                    len => Some(i.min(len - 1)),
+                });
+                state.song_state.select(None);
+            }
+            (Some(i), None) => {
+                state.deleting = Some(i);
+                app.message = format!("press d again to delete {}", app.playlists[i].name);
+            }
+            _ => {}
+        },
+        event::KeyCode::Char('t') => {
+            if let Some(i) = selected {
+                app.target_playlist = Some(app.playlists[i].name.clone());
+                app.message = format!(
+                    "p in search and library adds songs to {}",
+                    app.playlists[i].name
+                );
+            }
+        }
+        event::KeyCode::Char('e') => {
+            if let Some(i) = selected {
+                let name = app.playlists[i].name.clone();
+                app.export(&name, &songs);
+            }
+        }
+        event::KeyCode::Char('x') if app.playlist.is_some() => {
+            app.stop_playlist();
+            app.message = "playing the whole library".to_string();
+        }
+        event::KeyCode::Char('u') => {
+            app.toggle_unicode();
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('p') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn collections_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+    picker: &Picker,
+) {
+    if app.ui_dirty {
+        let current = |name: Option<&String>| {
+            if app.playlist.is_none() && app.collection.as_ref() == name {
+                " playing"
+            } else {
+                ""
+            }
+        };
+        let mut items = vec![ListItem::new(format!(
+            "All songs ({}){}",
+            app.json_item.len(),
+            current(None)
+        ))];
+        items.extend(app.collections.iter().map(|(name, count)| {
+            ListItem::new(format!("{} ({}){}", name, count, current(Some(name))))
+        }));
+        let list = Column {
+            title: format!("Collections ({})", app.collections.len()),
+            items,
+            focused: false,
+        };
+        app.collection_state.list_height = draw_screen(
+            terminal,
+            "Collections",
+            vec![(list, &mut app.collection_state.list_state)],
+            Footer::Hint(if app.collections.is_empty() {
+                "No collections in this library  Esc: back"
+            } else {
+                "Enter: play collection  Esc: back"
+            }),
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    let state = &mut app.collection_state;
+    // all songs come first
+    let total = app.collections.len() + 1;
+    let page = state.list_height.into();
+    if navigate_list(&mut state.list_state, total, page, key_event.code) {
+        return;
+    }
+    let selected = state.list_state.selected().filter(|&i| i < total);
+    match key_event.code {
+        event::KeyCode::Enter => {
+            if let Some(i) = selected {
+                let name = i.checked_sub(1).map(|i| app.collections[i].0.clone());
+                if app.start_collection(name.clone()) {
+                    app.play_idx(app.idx, mpv_control_tx.clone(), picker);
+                    app.ui_state = UIState::Main;
+                } else {
+                    app.message =
+                        format!("{} has no songs in the library", name.unwrap_or_default());
+                }
+            }
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('c') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn cues_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+) {
+    let key = app.json_item[app.idx].audio_hash.clone();
+    let count = app.cues.get(&key).len();
+    if app.ui_dirty {
+        let list = Column {
+            title: format!("{} {:.1} / {:.1}", app.title, app.progress, app.total),
+            items: app
+                .cues
+                .get(&key)
+                .iter()
+                .enumerate()
+                .map(|(i, cue)| {
+                    let number = if i < 9 {
+                        format!("{}. ", i + 1)
+                    } else {
+                        "   ".to_string()
+                    };
+                    ListItem::new(format!("{}{} {:.1}", number, cue.name, cue.time))
+                })
+                .collect(),
+            focused: false,
+        };
+        let footer = match &app.cue_state.prompt {
+            Some((NamePrompt::Create, input)) => Footer::prompt("New cue point here: ", input),
+            Some((NamePrompt::Rename, input)) => Footer::prompt("Rename to: ", input),
+            None => Footer::Hint(
+                "1-9/Enter: jump  n: new at current position  r: rename  d: delete  L: A-B loop  Esc: back",
+            ),
+        };
+        app.cue_state.list_height = draw_screen(
+            terminal,
+            "Cue points",
+            vec![(list, &mut app.cue_state.list_state)],
+            footer,
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    if app.cue_state.prompt.is_some() {
+        if let Some((prompt, input)) = type_name(&mut app.cue_state.prompt, key_event) {
+            let name = input.value().to_string();
+            match prompt {
+                NamePrompt::Create => {
+                    let i = app.add_cue(&name);
+                    app.cue_state.list_state.select(Some(i));
+                }
+                NamePrompt::Rename => {
+                    if let Some(i) = app.cue_state.list_state.selected() {
+                        app.rename_cue(i, &name);
+                    }
+                }
+            }
+        }
+        return;
+    }
+    let state = &mut app.cue_state;
+    let page = state.list_height.into();
+    if navigate_list(&mut state.list_state, count, page, key_event.code) {
+        return;
+    }
+    let selected = state.list_state.selected().filter(|&i| i < count);
+    match key_event.code {
+        event::KeyCode::Enter => {
+            if let Some(i) = selected {
+                app.jump_to_cue(i, mpv_control_tx.clone());
+            }
+        }
+        event::KeyCode::Char(c @ '1'..='9') => {
+            let i = c as usize - '1' as usize;
+            app.jump_to_cue(i, mpv_control_tx.clone());
+            if i < count {
+                app.cue_state.list_state.select(Some(i));
+            }
+        }
+        event::KeyCode::Char('n') => {
+            app.cue_state.prompt = Some((NamePrompt::Create, Input::default()));
+        }
+        event::KeyCode::Char('r') => {
+            if let Some(i) = selected {
+                let name = app.cues.get(&key)[i].name.clone();
+                app.cue_state.prompt = Some((NamePrompt::Rename, Input::new(name)));
+            }
+        }
+        event::KeyCode::Char('d') => {
+            if let Some(i) = selected {
+                app.delete_cue(i);
+                if i + 1 >= count {
+                    app.cue_state.list_state.select(i.checked_sub(1));
+                }
+            }
+        }
+        event::KeyCode::Char('L') => {
+            app.cycle_ab_loop(mpv_control_tx.clone());
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('k') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
+
+pub fn audio_devices_ui<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    mpv_control_tx: mpsc::Sender<InternalControl>,
+) {
+    if app.ui_dirty {
+        let list = Column {
+            title: format!("Audio devices ({})", app.audio_devices.len()),
+            items: app
+                .audio_devices
+                .iter()
+                .map(|device| {
+                    ListItem::new(format!(
+                        "{} ({}){}",
+                        device.description,
+                        device.name,
+                        if device.name == app.audio_device {
+                            " playing"
+                        } else {
+                            ""
+                        }
+                    ))
+                })
+                .collect(),
+            focused: false,
+        };
+        app.audio_device_state.list_height = draw_screen(
+            terminal,
+            "Audio devices",
+            vec![(list, &mut app.audio_device_state.list_state)],
+            Footer::Hint(if app.audio_devices.is_empty() {
+                "mpv found no audio devices  Esc: back"
+            } else {
+                "Enter: play on device  Esc: back"
+            }),
+            &app.message,
+        );
+        app.ui_dirty = false;
+    }
+    let Some(key_event) = read_key(app) else {
+        return;
+    };
+    let state = &mut app.audio_device_state;
+    let page = state.list_height.into();
+    if navigate_list(
+        &mut state.list_state,
+        app.audio_devices.len(),
+        page,
+        key_event.code,
+    ) {
+        return;
+    }
+    let selected = state
+        .list_state
+        .selected()
+        .filter(|&i| i < app.audio_devices.len());
+    match key_event.code {
+        event::KeyCode::Enter => {
+            if let Some(i) = selected {
+                let name = app.audio_devices[i].name.clone();
+                app.set_audio_device(&name, mpv_control_tx.clone());
+                app.ui_state = UIState::Main;
+            }
+        }
+        event::KeyCode::Esc | event::KeyCode::Char('o') => {
+            app.ui_state = UIState::Main;
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,38 @@
+// Playback state saved to the XDG state directory, so that `--resume` could
+// continue from where the last run stopped. Songs are stored by their audio
+// file key, as indexes change when the library changes.
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ShuffleMode;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Session {
+    pub current: String,
+    /// Playback position of current song, in seconds
+    pub position: f64,
+    pub queue: Vec<String>,
+    pub order: Vec<String>,
+    pub shuffle: ShuffleMode,
+    pub repeat: bool,
+    pub unicode: bool,
+    pub favourites_only: bool,
+    pub start_at_preview: bool,
+    pub previewing: bool,
+    /// Name of the playlist being played
+    pub playlist: Option<String>,
+    /// Name of the osu! collection being played
+    pub collection: Option<String>,
+}
+
+impl Session {
+    /// None when there is no session or it cannot be read. Unlike stats or ratings,
+    /// a lost session is not worth keeping the file for.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+}
@@ -0,0 +1,504 @@
+// Reader for osu!stable's osu!.db and collection.db.
+// Format reference: https://github.com/ppy/osu/wiki/Legacy-database-file-structure
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{FileLayout, JsonItem, Metadata, beatmap, local::find_file};
+
+// osu!.db changed some fields at these versions
+const VERSION_FLOAT_DIFFICULTY: i32 = 20140609;
+const VERSION_NO_ENTRY_SIZE: i32 = 20191106;
+const VERSION_FLOAT_STAR_RATING: i32 = 20250107;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+struct Reader {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Reader {
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            buf: std::fs::read(path)?,
+            pos: 0,
+        })
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| invalid("unexpected end of osu! database"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Reads an element count, which a corrupt file could make negative
+    fn count(&mut self) -> io::Result<usize> {
+        let count = self.i32()?;
+        usize::try_from(count).map_err(|_| invalid(format!("bad count {}", count)))
+    }
+
+    fn uleb128(&mut self) -> io::Result<usize> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            if shift >= usize::BITS {
+                return Err(invalid("bad string length"));
+            }
+            let byte = self.u8()?;
+            result |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        match self.u8()? {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let len = self.uleb128()?;
+                Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+            }
+            b => Err(invalid(format!("bad string marker {:#x}", b))),
+        }
+    }
+}
+
+struct Beatmap {
+    artist: String,
+    artist_unicode: String,
+    title: String,
+    title_unicode: String,
+    audio_file: String,
+    md5: String,
+    osu_file: String,
+    source: String,
+    tags: String,
+    folder: String,
+    total_time: i32,
+}
+
+fn read_beatmap(r: &mut Reader, version: i32) -> io::Result<Beatmap> {
+    if version < VERSION_NO_ENTRY_SIZE {
+        r.skip(4)?;
+    }
+    let artist = r.string()?;
+    let artist_unicode = r.string()?;
+    let title = r.string()?;
+    let title_unicode = r.string()?;
+    let _creator = r.string()?;
+    let _difficulty = r.string()?;
+    let audio_file = r.string()?;
+    let md5 = r.string()?;
+    let osu_file = r.string()?;
+    // ranked status, circle/slider/spinner count, modification time
+    r.skip(1 + 2 * 3 + 8)?;
+    if version < VERSION_FLOAT_DIFFICULTY {
+        r.skip(4)?;
+    } else {
+        r.skip(4 * 4)?;
+    }
+    // slider velocity
+    r.skip(8)?;
+    if version >= VERSION_FLOAT_DIFFICULTY {
+        // star ratings of four modes, as (0x08, int, 0x0d, double) or (0x08, int, 0x0c, float) pairs
+        let pair_size = if version >= VERSION_FLOAT_STAR_RATING {
+            1 + 4 + 1 + 4
+        } else {
+            1 + 4 + 1 + 8
+        };
+        for _ in 0..4 {
+            let count = r.count()?;
+            r.skip(count * pair_size)?;
+        }
+    }
+    // drain time, total time (ms), preview time
+    r.skip(4)?;
+    let total_time = r.i32()?;
+    r.skip(4)?;
+    let timing_points = r.count()?;
+    r.skip(timing_points * (8 + 8 + 1))?;
+    // difficulty id, beatmap id, thread id, grades, local offset, stack leniency, mode
+    r.skip(4 * 3 + 4 + 2 + 4 + 1)?;
+    let source = r.string()?;
+    let tags = r.string()?;
+    // online offset
+    r.skip(2)?;
+    let _title_font = r.string()?;
+    // unplayed, last played, is osz2
+    r.skip(1 + 8 + 1)?;
+    let folder = r.string()?;
+    // last checked, ignore sound/skin, disable storyboard/video, visual override
+    r.skip(8 + 5)?;
+    if version < VERSION_FLOAT_DIFFICULTY {
+        r.skip(2)?;
+    }
+    // last modification time, mania scroll speed
+    r.skip(4 + 1)?;
+    Ok(Beatmap {
+        artist,
+        artist_unicode,
+        title,
+        title_unicode,
+        audio_file,
+        md5,
+        osu_file,
+        source,
+        tags,
+        folder,
+        total_time,
+    })
+}
+
+fn read_osu_db(path: &Path) -> io::Result<Vec<Beatmap>> {
+    read_beatmaps(Reader::open(path)?)
+}
+
+fn read_beatmaps(mut r: Reader) -> io::Result<Vec<Beatmap>> {
+    let version = r.i32()?;
+    // folder count, account unlocked, unlock date
+    r.skip(4 + 1 + 8)?;
+    let _player = r.string()?;
+    let count = r.count()?;
+    // every entry takes more than a byte, so a bad count cannot allocate more than the file size
+    let mut beatmaps = Vec::with_capacity(count.min(r.buf.len()));
+    for _ in 0..count {
+        beatmaps.push(read_beatmap(&mut r, version)?);
+    }
+    Ok(beatmaps)
+}
+
+/// Returns beatmap MD5 hashes of each collection in collection.db
+fn read_collection_db(path: &Path) -> io::Result<Vec<(String, Vec<String>)>> {
+    let mut r = Reader::open(path)?;
+    let _version = r.i32()?;
+    let count = r.count()?;
+    let mut collections = Vec::with_capacity(count.min(r.buf.len()));
+    for _ in 0..count {
+        let name = r.string()?;
+        let hashes = (0..r.count()?)
+            .map(|_| r.string())
+            .collect::<io::Result<Vec<_>>>()?;
+        collections.push((name, hashes));
+    }
+    Ok(collections)
+}
+
+/// Reads songs from an osu!stable install, grouping difficulties by beatmap set folder,
+/// with the collections (from collection.db) they are in.
+/// With `collections` given, only songs in these collections are used.
+pub fn load_songs(
+    osu_db: &Path,
+    songs_path: &Path,
+    collections: &[String],
+    beatmap_cache: &mut beatmap::Cache,
+) -> io::Result<Vec<JsonItem>> {
+    let beatmaps = read_osu_db(osu_db)?;
+    let collection_db = osu_db.with_file_name("collection.db");
+    // collection.db is only required to filter by collection
+    let collection_list = if collections.is_empty() {
+        read_collection_db(&collection_db).unwrap_or_default()
+    } else {
+        read_collection_db(&collection_db)?
+            .into_iter()
+            .filter(|(name, _)| collections.contains(name))
+            .collect()
+    };
+    // collection names of each beatmap MD5
+    let mut in_collections: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, hashes) in collection_list {
+        for hash in hashes {
+            in_collections.entry(hash).or_default().push(name.clone());
+        }
+    }
+
+    let mut set_index: HashMap<String, usize> = HashMap::new();
+    let mut items: Vec<JsonItem> = Vec::new();
+    // set folder and .osu files of each item
+    let mut folders: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    for beatmap in beatmaps {
+        let beatmap_collections = in_collections.get(&beatmap.md5);
+        if beatmap.audio_file.is_empty()
+            || (!collections.is_empty() && beatmap_collections.is_none())
+        {
+            continue;
+        }
+        let osu_file = songs_path.join(&beatmap.folder).join(&beatmap.osu_file);
+        let length = u32::try_from(beatmap.total_time).ok().filter(|&l| l > 0);
+        let i = match set_index.get(&beatmap.folder) {
+            Some(&i) => i,
+            None => {
+                set_index.insert(beatmap.folder.clone(), items.len());
+                folders.push((beatmap.folder.clone(), Vec::new()));
+                let audio_file = find_file(&songs_path.join(&beatmap.folder), &beatmap.audio_file)
+                    .unwrap_or(beatmap.audio_file);
+                items.push(JsonItem {
+                    audio_hash: format!("{}/{}", beatmap.folder, audio_file),
+                    bg_hashes: Vec::new(),
+                    metadata: Metadata {
+                        title: beatmap.title,
+                        title_unicode: beatmap.title_unicode,
+                        artist: beatmap.artist,
+                        artist_unicode: beatmap.artist_unicode,
+                        source: beatmap.source,
+                        tags: beatmap.tags,
+                        ..Default::default()
+                    },
+                    layout: FileLayout::Relative(songs_path.to_path_buf()),
+                });
+                items.len() - 1
+            }
+        };
+        folders[i].1.push(osu_file);
+        for name in beatmap_collections.into_iter().flatten() {
+            if !items[i].metadata.collections.contains(name) {
+                items[i].metadata.collections.push(name.clone());
+            }
+        }
+        if let Some(length) = length {
+            let metadata = &mut items[i].metadata;
+            metadata.length = Some(metadata.length.map_or(length, |l| l.max(length)));
+        }
+    }
+    // osu!.db has no background, so take it from .osu files
+    for (item, (folder, osu_files)) in items.iter_mut().zip(folders) {
+        let dir = songs_path.join(&folder);
+        beatmap_cache.enrich(item, &osu_files, |name| {
+            find_file(&dir, name).map(|file| format!("{}/{}", folder, file))
+        });
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const VERSION: i32 = 20250107;
+    // before entry sizes were dropped, with integer difficulty and double star ratings
+    const OLD_VERSION: i32 = 20140101;
+
+    #[derive(Default)]
+    struct Writer(Vec<u8>);
+
+    impl Writer {
+        fn i32(&mut self, v: i32) {
+            self.0.extend_from_slice(&v.to_le_bytes());
+        }
+
+        fn zeros(&mut self, n: usize) {
+            self.0.resize(self.0.len() + n, 0);
+        }
+
+        fn string(&mut self, s: &str) {
+            if s.is_empty() {
+                self.0.push(0x00);
+                return;
+            }
+            self.0.push(0x0b);
+            let mut len = s.len();
+            while len >= 0x80 {
+                self.0.push((len & 0x7f) as u8 | 0x80);
+                len >>= 7;
+            }
+            self.0.push(len as u8);
+            self.0.extend_from_slice(s.as_bytes());
+        }
+
+        /// Writes an osu!.db entry, with the fields not read left zero
+        fn beatmap(&mut self, version: i32, folder: &str, audio: &str, md5: &str, total_time: i32) {
+            if version < VERSION_NO_ENTRY_SIZE {
+                self.zeros(4);
+            }
+            for s in ["Artist", "アーティスト", "Song", "歌", "mapper", "Hard"] {
+                self.string(s);
+            }
+            self.string(audio);
+            self.string(md5);
+            self.string("song (Hard).osu");
+            self.zeros(1 + 2 * 3 + 8);
+            self.zeros(if version < VERSION_FLOAT_DIFFICULTY {
+                4
+            } else {
+                4 * 4
+            });
+            self.zeros(8);
+            if version >= VERSION_FLOAT_DIFFICULTY {
+                for _ in 0..4 {
+                    // one star rating per mode
+                    self.i32(1);
+                    self.0.push(0x08);
+                    self.i32(0);
+                    if version >= VERSION_FLOAT_STAR_RATING {
+                        self.0.push(0x0c);
+                        self.zeros(4);
+                    } else {
+                        self.0.push(0x0d);
+                        self.zeros(8);
+                    }
+                }
+            }
+            self.zeros(4);
+            self.i32(total_time);
+            self.zeros(4);
+            // one timing point
+            self.i32(1);
+            self.zeros(8 + 8 + 1);
+            self.zeros(4 * 3 + 4 + 2 + 4 + 1);
+            self.string("Game");
+            self.string("tag1 tag2");
+            self.zeros(2);
+            self.string("");
+            self.zeros(1 + 8 + 1);
+            self.string(folder);
+            self.zeros(8 + 5);
+            if version < VERSION_FLOAT_DIFFICULTY {
+                self.zeros(2);
+            }
+            self.zeros(4 + 1);
+        }
+    }
+
+    /// An osu!.db with two difficulties of one set, one of another and one without audio
+    fn osu_db(version: i32) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.i32(version);
+        w.zeros(4 + 1 + 8);
+        w.string("player");
+        w.i32(4);
+        w.beatmap(version, "1 Artist - Song", "audio.mp3", "a1", 90000);
+        w.beatmap(version, "1 Artist - Song", "audio.mp3", "a2", 120000);
+        w.beatmap(version, "2 Other - Tune", "tune.ogg", "b1", 60000);
+        w.beatmap(version, "3 Broken", "", "c1", 60000);
+        w.0
+    }
+
+    fn collection_db() -> Vec<u8> {
+        let mut w = Writer::default();
+        w.i32(VERSION);
+        w.i32(2);
+        w.string("Favourites");
+        w.i32(1);
+        w.string("a2");
+        w.string("Empty");
+        w.i32(0);
+        w.0
+    }
+
+    #[test]
+    fn reads_osu_db() {
+        for version in [VERSION, VERSION_FLOAT_STAR_RATING - 1, OLD_VERSION] {
+            let beatmaps = read_beatmaps(Reader {
+                buf: osu_db(version),
+                pos: 0,
+            })
+            .unwrap();
+            assert_eq!(beatmaps.len(), 4, "version {}", version);
+            let beatmap = &beatmaps[1];
+            assert_eq!(beatmap.artist, "Artist");
+            assert_eq!(beatmap.artist_unicode, "アーティスト");
+            assert_eq!(beatmap.title_unicode, "歌");
+            assert_eq!(beatmap.audio_file, "audio.mp3");
+            assert_eq!(beatmap.md5, "a2");
+            assert_eq!(beatmap.osu_file, "song (Hard).osu");
+            assert_eq!(beatmap.source, "Game");
+            assert_eq!(beatmap.tags, "tag1 tag2");
+            assert_eq!(beatmap.folder, "1 Artist - Song");
+            assert_eq!(beatmap.total_time, 120000);
+        }
+    }
+
+    #[test]
+    fn loads_songs() {
+        let dir = std::env::temp_dir().join(format!("ccp-stable-{}", std::process::id()));
+        let set = dir.join("Songs").join("1 Artist - Song");
+        fs::create_dir_all(&set).unwrap();
+        fs::write(dir.join("osu!.db"), osu_db(VERSION)).unwrap();
+        fs::write(dir.join("collection.db"), collection_db()).unwrap();
+        // osu!.db has no background, it comes from the .osu file
+        fs::write(
+            set.join("song (Hard).osu"),
+            "osu file format v14\n\n[Metadata]\nCreator:mapper\n\n[Events]\n0,0,\"bg.jpg\",0,0\n",
+        )
+        .unwrap();
+        // file names are matched case-insensitively, like osu! does
+        fs::write(set.join("BG.jpg"), "").unwrap();
+        fs::write(set.join("Audio.MP3"), "").unwrap();
+
+        let load = |collections: &[&str]| {
+            let collections: Vec<_> = collections.iter().map(|c| c.to_string()).collect();
+            load_songs(
+                &dir.join("osu!.db"),
+                &dir.join("Songs"),
+                &collections,
+                &mut beatmap::Cache::default(),
+            )
+        };
+        let all = load(&[]).unwrap();
+        let favourites = load(&["Favourites"]).unwrap();
+        let empty = load(&["Empty"]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(all.len(), 2);
+        let song = &all[0];
+        assert_eq!(song.audio_hash, "1 Artist - Song/Audio.MP3");
+        assert_eq!(song.bg_hashes, ["1 Artist - Song/BG.jpg"]);
+        assert_eq!(song.metadata.title, "Song");
+        assert_eq!(song.metadata.creator, "mapper");
+        assert_eq!(song.metadata.length, Some(120000));
+        assert_eq!(song.metadata.collections, ["Favourites"]);
+        assert_eq!(all[1].audio_hash, "2 Other - Tune/tune.ogg");
+        assert!(all[1].metadata.collections.is_empty());
+
+        assert_eq!(favourites.len(), 1);
+        assert_eq!(favourites[0].audio_hash, song.audio_hash);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_or_corrupt_db() {
+        let buf = osu_db(VERSION);
+        let read = |buf: &[u8]| {
+            read_beatmaps(Reader {
+                buf: buf.to_vec(),
+                pos: 0,
+            })
+        };
+        assert_eq!(read(&buf).unwrap().len(), 4);
+        for len in 0..buf.len() {
+            assert!(read(&buf[..len]).is_err(), "length {}", len);
+        }
+
+        // a negative beatmap count
+        let mut corrupt = buf.clone();
+        let count = 4 + 4 + 1 + 8 + 1 + 1 + "player".len();
+        corrupt[count..count + 4].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(read(&corrupt).is_err());
+    }
+}
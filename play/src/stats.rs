@@ -0,0 +1,163 @@
+// Per-song listening statistics, kept in the XDG data directory and keyed by
+// audio file key. Title, artist and source are stored alongside, so that
+// `play stats` works without loading the library.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How playback of a song ended
+pub enum End {
+    /// Played to the end
+    Completed,
+    /// Skipped to the next song
+    Skipped,
+    /// Anything else, like choosing another song or quitting
+    Switched,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct SongStats {
+    pub title: String,
+    pub artist: String,
+    pub source: String,
+    /// Times started
+    pub plays: u32,
+    pub completed: u32,
+    pub skips: u32,
+    /// Seconds actually listened
+    pub listen_time: f64,
+    /// Unix timestamp
+    pub last_played: Option<u64>,
+    /// In seconds, as reported by mpv
+    pub length: Option<f64>,
+}
+
+impl SongStats {
+    pub fn last_played(&self) -> Option<SystemTime> {
+        self.last_played
+            .map(|secs| UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Stats {
+    pub songs: HashMap<String, SongStats>,
+}
+
+impl Stats {
+    pub fn get(&self, key: &str) -> Option<&SongStats> {
+        self.songs.get(key)
+    }
+
+    pub fn start(&mut self, key: &str, title: &str, artist: &str, source: &str) {
+        let song = self.songs.entry(key.to_string()).or_default();
+        song.title = title.to_string();
+        song.artist = artist.to_string();
+        song.source = source.to_string();
+        song.plays += 1;
+        song.last_played = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+    }
+
+    pub fn set_length(&mut self, key: &str, length: f64) {
+        if let Some(song) = self.songs.get_mut(key) {
+            song.length = Some(length);
+        }
+    }
+
+    pub fn finish(&mut self, key: &str, listened: f64, end: End) {
+        let song = self.songs.entry(key.to_string()).or_default();
+        song.listen_time += listened;
+        match end {
+            End::Completed => song.completed += 1,
+            End::Skipped => song.skips += 1,
+            End::Switched => {}
+        }
+    }
+}
+
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs as u64;
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m {}s", secs / 60, secs % 60)
+    }
+}
+
+#[derive(Default)]
+struct Total {
+    plays: u32,
+    listen_time: f64,
+}
+
+fn print_top(heading: &str, totals: HashMap<String, Total>, limit: usize) {
+    let mut totals: Vec<(String, Total)> = totals
+        .into_iter()
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    totals.sort_by(|a, b| {
+        b.1.plays
+            .cmp(&a.1.plays)
+            .then(b.1.listen_time.total_cmp(&a.1.listen_time))
+    });
+    println!("{}:", heading);
+    for (name, total) in totals.iter().take(limit) {
+        println!(
+            "{:>6} plays {:>10}  {}",
+            total.plays,
+            format_duration(total.listen_time),
+            name
+        );
+    }
+    println!();
+}
+
+/// Prints top songs, artists and sources, for `play stats`
+pub fn print(stats: &Stats, limit: usize) {
+    let mut songs: Vec<&SongStats> = stats.songs.values().collect();
+    songs.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.listen_time.total_cmp(&a.listen_time))
+    });
+    let total_time: f64 = songs.iter().map(|s| s.listen_time).sum();
+    println!(
+        "{} songs played, {} listened in total\n",
+        songs.len(),
+        format_duration(total_time)
+    );
+
+    println!("Top songs:");
+    for song in songs.iter().take(limit) {
+        println!(
+            "{:>6} plays {:>10}  {} - {} ({} completed, {} skipped)",
+            song.plays,
+            format_duration(song.listen_time),
+            song.title,
+            song.artist,
+            song.completed,
+            song.skips
+        );
+    }
+    println!();
+
+    let mut artists: HashMap<String, Total> = HashMap::new();
+    let mut sources: HashMap<String, Total> = HashMap::new();
+    for song in &songs {
+        for (totals, name) in [(&mut artists, &song.artist), (&mut sources, &song.source)] {
+            let total = totals.entry(name.clone()).or_default();
+            total.plays += song.plays;
+            total.listen_time += song.listen_time;
+        }
+    }
+    print_top("Top artists", artists, limit);
+    print_top("Top sources", sources, limit);
+}
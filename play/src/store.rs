@@ -0,0 +1,128 @@
+// Loading and saving of the JSON files the player keeps its data in. A file
+// that cannot be parsed is moved aside rather than overwritten by the next
+// save, so that a bad edit or a file from another version loses nothing.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Where a file that cannot be parsed is moved to, like "beatmaps.json.1700000000.bad"
+fn aside_path(path: &Path) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".{}.bad", secs));
+    aside.into()
+}
+
+/// Loads a file, or the default when it does not exist (yet) or cannot be parsed
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("cannot read {}: {}", path.display(), e);
+            }
+            return T::default();
+        }
+    };
+    match serde_json::from_str(&content) {
+        Ok(value) => value,
+        Err(e) => {
+            let aside = aside_path(path);
+            eprintln!(
+                "cannot parse {}: {}, moving it to {}",
+                path.display(),
+                e,
+                aside.display()
+            );
+            if let Err(e) = fs::rename(path, &aside) {
+                eprintln!("cannot move {}: {}", path.display(), e);
+            }
+            T::default()
+        }
+    }
+}
+
+fn write(path: &Path, content: String) -> io::Result<()> {
+    // write to a temporary file first, so that a crash while saving keeps the old file
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    write(
+        path,
+        serde_json::to_string(value).map_err(io::Error::other)?,
+    )
+}
+
+/// For files meant to be edited by hand
+pub fn save_json_pretty<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    write(
+        path,
+        serde_json::to_string_pretty(value).map_err(io::Error::other)?,
+    )
+}
+
+/// Saves to a file placed by xdg (like with `place_data_file`), logging errors
+/// of creating its directory or of `save`
+pub fn save_placed(
+    what: &str,
+    path: io::Result<PathBuf>,
+    save: impl FnOnce(&Path) -> io::Result<()>,
+) {
+    if let Err(e) = path.and_then(|path| save(&path)) {
+        eprintln!("cannot save {}: {}", what, e);
+    }
+}
+
+/// Size and modification time, which change when a file is downloaded again or updated
+pub fn file_stamp(path: &Path) -> io::Result<String> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    Ok(format!("{} {}", metadata.len(), mtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn bad_file_is_moved_aside() {
+        let dir = std::env::temp_dir().join(format!("ccp-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stats.json");
+
+        assert!(load_json::<HashMap<String, u32>>(&path).is_empty());
+
+        fs::write(&path, "{\"a\": 1").unwrap();
+        assert!(load_json::<HashMap<String, u32>>(&path).is_empty());
+        assert!(!path.exists());
+        let aside: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(fs::read_to_string(&aside[0]).unwrap(), "{\"a\": 1");
+
+        let songs = HashMap::from([("a".to_string(), 1)]);
+        save_json(&path, &songs).unwrap();
+        assert_eq!(load_json::<HashMap<String, u32>>(&path), songs);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}